@@ -0,0 +1,353 @@
+//!
+//! Throughput and hit-rate comparison between `S3FIFO` and a plain LRU,
+//! plus the built-in `EvictionStrategy` variants on the Zipf workload,
+//! batched vs. individual puts through a lock, and the ghost queue's
+//! memory footprint and insert cost.
+//!
+//! Run with `cargo bench`; criterion keeps the previous run around and
+//! reports regressions against it. The Zipf workload can be tuned through
+//! the `S3FIFO_BENCH_SKEW` and `S3FIFO_BENCH_CACHE_SIZE` environment
+//! variables. Hit rates don't vary between iterations, so they are printed
+//! once per workload rather than measured.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hint::black_box;
+use std::mem::size_of;
+use std::sync::Mutex;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use s3fifo::prelude::*;
+
+const OPS: usize = 20_000;
+const KEY_SPACE: usize = 100_000;
+
+/// xorshift64*, good enough to generate workloads.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Samples keys `0..n` where key `i` has weight `1 / (i + 1)^skew`.
+struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, skew: f64) -> Zipf {
+        let mut cdf = Vec::with_capacity(n);
+        let mut total = 0.0;
+        for i in 0..n {
+            total += 1.0 / ((i + 1) as f64).powf(skew);
+            cdf.push(total);
+        }
+        for c in cdf.iter_mut() {
+            *c /= total;
+        }
+        Zipf { cdf }
+    }
+
+    fn sample(&self, rng: &mut XorShift) -> usize {
+        let u = rng.unit();
+        self.cdf.partition_point(|&c| c < u).min(self.cdf.len() - 1)
+    }
+}
+
+/// Minimal LRU: a hash map plus an ordered index of access stamps.
+struct LruCache<K, V> {
+    capacity: usize,
+    clock: u64,
+    map: HashMap<K, (V, u64)>,
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Clone + Eq + std::hash::Hash, V> LruCache<K, V> {
+    fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache { capacity, clock: 0, map: HashMap::new(), order: BTreeMap::new() }
+    }
+
+    fn get(&mut self, key: &K) -> Option<&V> {
+        self.clock += 1;
+        let (_, stamp) = self.map.get_mut(key)?;
+        let key = self.order.remove(stamp).unwrap();
+        *stamp = self.clock;
+        self.order.insert(self.clock, key.clone());
+        self.map.get(&key).map(|(value, _)| value)
+    }
+
+    fn put(&mut self, key: K, value: V) {
+        self.clock += 1;
+        if let Some((_, stamp)) = self.map.insert(key.clone(), (value, self.clock)) {
+            self.order.remove(&stamp);
+        } else if self.map.len() > self.capacity {
+            let (_, oldest) = self.order.pop_first().unwrap();
+            self.map.remove(&oldest);
+        }
+        self.order.insert(self.clock, key);
+    }
+}
+
+/// The operations every workload needs from a cache.
+trait Cache {
+    fn get(&mut self, key: usize) -> bool;
+    fn put(&mut self, key: usize);
+}
+
+impl Cache for S3FIFO<usize, usize> {
+    fn get(&mut self, key: usize) -> bool {
+        S3FIFO::get(self, &key).is_some()
+    }
+
+    fn put(&mut self, key: usize) {
+        S3FIFO::put(self, key, key);
+    }
+}
+
+impl Cache for LruCache<usize, usize> {
+    fn get(&mut self, key: usize) -> bool {
+        LruCache::get(self, &key).is_some()
+    }
+
+    fn put(&mut self, key: usize) {
+        LruCache::put(self, key, key);
+    }
+}
+
+enum Op {
+    Get(usize),
+    Put(usize),
+    /// Read-through: get, and put on a miss.
+    Access(usize),
+}
+
+/// Run `ops` against `cache` and return `(hits, lookups)`.
+fn run<C: Cache>(cache: &mut C, ops: &[Op]) -> (usize, usize) {
+    let mut hits = 0;
+    let mut lookups = 0;
+    for op in ops {
+        match *op {
+            Op::Get(key) => {
+                lookups += 1;
+                hits += black_box(cache.get(key)) as usize;
+            }
+            Op::Put(key) => cache.put(key),
+            Op::Access(key) => {
+                lookups += 1;
+                if black_box(cache.get(key)) {
+                    hits += 1;
+                } else {
+                    cache.put(key);
+                }
+            }
+        }
+    }
+    (hits, lookups)
+}
+
+/// Hit rate of a fresh cache over `ops`, `-` for a workload without lookups.
+fn hit_rate<C: Cache>(mut cache: C, ops: &[Op]) -> String {
+    match run(&mut cache, ops) {
+        (_, 0) => String::from("-"),
+        (hits, lookups) => format!("{:.2}%", 100.0 * hits as f64 / lookups as f64),
+    }
+}
+
+/// Time `ops` against a fresh cache from `make` per iteration.
+fn bench_ops<C: Cache>(
+    group: &mut criterion::BenchmarkGroup<'_, criterion::measurement::WallTime>,
+    name: &str,
+    make: impl Fn() -> C,
+    ops: &[Op],
+) {
+    group.bench_function(name, |b| {
+        b.iter_batched(&make, |mut cache| run(&mut cache, ops), BatchSize::LargeInput)
+    });
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// `(cache_size, zipf skew)` from the environment.
+fn params() -> (usize, f64) {
+    (env_or("S3FIFO_BENCH_CACHE_SIZE", 1000), env_or("S3FIFO_BENCH_SKEW", 1.0))
+}
+
+fn zipf_ops(zipf: &Zipf, rng: &mut XorShift) -> Vec<Op> {
+    (0..OPS).map(|_| Op::Access(zipf.sample(rng))).collect()
+}
+
+fn s3fifo_vs_lru(c: &mut Criterion) {
+    let (cache_size, skew) = params();
+    let mut rng = XorShift::new(42);
+
+    let sequential: Vec<Op> = (0..OPS).map(Op::Put).collect();
+    let random: Vec<Op> = (0..OPS)
+        .map(|_| rng.below(cache_size * 2))
+        .map(Op::Access)
+        .collect();
+    let zipf = Zipf::new(KEY_SPACE, skew);
+    let zipfian = zipf_ops(&zipf, &mut rng);
+    let mixed: Vec<Op> = (0..OPS)
+        .map(|_| {
+            let key = zipf.sample(&mut rng);
+            if rng.below(10) < 8 { Op::Get(key) } else { Op::Put(key) }
+        })
+        .collect();
+
+    println!("cache_size = {}, zipf skew = {}, {} ops per iteration", cache_size, skew, OPS);
+    let workloads = [
+        ("sequential insert", &sequential),
+        ("random get", &random),
+        ("zipf", &zipfian),
+        ("mixed 80% read / 20% write", &mixed),
+    ];
+    for (name, ops) in workloads {
+        let make_s3fifo = || S3FIFO::try_new(cache_size, 0.1).unwrap();
+        let make_lru = || LruCache::new(cache_size);
+        println!(
+            "{:<40} hit rate s3fifo {:>8}, lru {:>8}",
+            name, hit_rate(make_s3fifo(), ops), hit_rate(make_lru(), ops)
+        );
+
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(ops.len() as u64));
+        bench_ops(&mut group, "s3fifo", make_s3fifo, ops);
+        bench_ops(&mut group, "lru", make_lru, ops);
+        group.finish();
+    }
+}
+
+fn strategies(c: &mut Criterion) {
+    let (cache_size, skew) = params();
+    let zipfian = zipf_ops(&Zipf::new(KEY_SPACE, skew), &mut XorShift::new(42));
+
+    let mut group = c.benchmark_group("strategy / zipf");
+    group.throughput(Throughput::Elements(zipfian.len() as u64));
+    let strategies = [
+        ("s3fifo", EvictionStrategy::S3FIFO),
+        ("fifo", EvictionStrategy::PureFIFO),
+        ("lru", EvictionStrategy::LRU),
+        ("mru", EvictionStrategy::MRU),
+    ];
+    for (name, strategy) in strategies {
+        let make = || S3FIFO::with_strategy(cache_size, 0.1, strategy);
+        println!("{:<40} hit rate {:>8}", format!("strategy {} / zipf", name), hit_rate(make(), &zipfian));
+        bench_ops(&mut group, name, make, &zipfian);
+    }
+    group.finish();
+}
+
+/// 1000 puts through a `Mutex`: one lock per put vs. one `apply_batch`.
+fn batch(c: &mut Criterion) {
+    const BATCH: usize = 1000;
+    let (cache_size, _) = params();
+    let make = || Mutex::new(S3FIFO::try_new(cache_size, 0.1).unwrap());
+
+    let mut group = c.benchmark_group("locked put");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    group.bench_function(BenchmarkId::from_parameter("individual"), |b| {
+        b.iter_batched(
+            make,
+            |cache| {
+                for key in 0..BATCH {
+                    cache.lock().unwrap().put(key, key);
+                }
+                cache
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function(BenchmarkId::from_parameter("apply_batch"), |b| {
+        b.iter_batched(
+            make,
+            |cache| {
+                let ops = (0..BATCH).map(|key| CacheOp::Put(key, key));
+                black_box(cache.lock().unwrap().apply_batch(ops));
+                cache
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+/// A value big enough that copying it shows up.
+type Blob = [u64; 16];
+
+///
+/// The ghost queue only remembers keys. Prints what it holds after a
+/// scan against what it would hold if it kept the evicted values too, as
+/// it did before it became a `FIFOCache<K, ()>`, then times the scan:
+/// every insert retires a key to the ghost queue.
+fn ghost_memory(c: &mut Criterion) {
+    let (cache_size, _) = params();
+    let scan = |cache: &mut S3FIFO<usize, Blob>| {
+        for key in 0..OPS {
+            cache.put(key, [key as u64; 16]);
+        }
+    };
+
+    let mut cache = S3FIFO::try_new(cache_size, 0.1).unwrap();
+    scan(&mut cache);
+    let ghosts = cache.segment_info().ghost_len;
+    let keys_only = ghosts * size_of::<usize>();
+    let with_values = ghosts * (size_of::<usize>() + size_of::<Blob>());
+    println!(
+        "ghost queue: {} keys, {} bytes of keys, {} bytes if values were kept ({:.1}x); \
+         whole cache {} bytes",
+        ghosts, keys_only, with_values, with_values as f64 / keys_only as f64,
+        cache.mem_usage_bytes()
+    );
+
+    let mut group = c.benchmark_group("ghost queue");
+    group.throughput(Throughput::Elements(OPS as u64));
+    group.bench_function("scan / 128-byte values", |b| {
+        b.iter_batched(
+            || S3FIFO::<usize, Blob>::try_new(cache_size, 0.1).unwrap(),
+            |mut cache| {
+                scan(&mut cache);
+                cache
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+/// Streaming inserts with and without ghost tracking.
+fn ghost_disabled(c: &mut Criterion) {
+    let (cache_size, _) = params();
+    let sequential: Vec<Op> = (0..OPS).map(Op::Put).collect();
+
+    let mut group = c.benchmark_group("sequential insert / ghost");
+    group.throughput(Throughput::Elements(sequential.len() as u64));
+    bench_ops(&mut group, "enabled", || S3FIFO::try_new(cache_size, 0.1).unwrap(), &sequential);
+    bench_ops(
+        &mut group,
+        "disabled",
+        || S3FIFO::with_ghost_disabled(cache_size, 0.1),
+        &sequential
+    );
+    group.finish();
+}
+
+criterion_group!(benches, s3fifo_vs_lru, strategies, batch, ghost_memory, ghost_disabled);
+criterion_main!(benches);
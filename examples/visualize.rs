@@ -0,0 +1,21 @@
+use s3fifo::prelude::*;
+
+// Fill a small cache with a mix of one-hit and repeatedly read keys, then
+// print its queues as a Graphviz graph:
+//
+//     cargo run --example visualize | dot -Tsvg > s3fifo.svg
+
+fn main() {
+    let mut cache: S3FIFO<usize, usize> = S3FIFO::new_with_explicit_sizes(3, 6, 6);
+
+    for key in 0..20 {
+        cache.put(key, key * 10);
+        if key % 3 == 0 {
+            // Read twice so the entry is promoted to the main queue.
+            cache.get(&key);
+            cache.get(&key);
+        }
+    }
+
+    println!("{}", cache.print_dot());
+}
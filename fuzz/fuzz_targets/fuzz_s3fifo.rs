@@ -0,0 +1,58 @@
+//!
+//! Runs an arbitrary sequence of operations against a small `S3FIFO` and
+//! checks its internal invariants after every step.
+//!
+//! Input layout: one opcode byte (taken modulo the number of operations)
+//! followed by its arguments, one byte each. A truncated tail is ignored.
+
+#![no_main]
+
+use std::num::NonZeroUsize;
+
+use libfuzzer_sys::fuzz_target;
+use s3fifo::prelude::*;
+
+const CACHE_SIZE: NonZeroUsize = NonZeroUsize::new(5).unwrap();
+const SMALL_RATIO: f64 = 0.4;
+
+fuzz_target!(|data: &[u8]| {
+    let mut cache: S3FIFO<u8, u8> = S3FIFO::new(CACHE_SIZE, SMALL_RATIO);
+    let mut bytes = data.iter().copied();
+
+    while let Some(op) = bytes.next() {
+        match op % 6 {
+            0 => {
+                let (Some(key), Some(value)) = (bytes.next(), bytes.next()) else { break };
+                cache.put(key, value);
+                assert_eq!(cache.get(&key), Some(&value));
+            }
+            1 => {
+                let Some(key) = bytes.next() else { break };
+                cache.get(&key);
+            }
+            2 => {
+                let Some(key) = bytes.next() else { break };
+                cache.take(&key);
+                assert!(cache.get(&key).is_none());
+            }
+            3 => {
+                // Touch: an access whose result is thrown away.
+                let Some(key) = bytes.next() else { break };
+                let _ = cache.get_or_ghost(&key);
+            }
+            4 => {
+                let Some(n) = bytes.next() else { break };
+                let before = cache.len();
+                let evicted = cache.evict_n(n as usize);
+                assert_eq!(cache.len(), before - evicted);
+            }
+            _ => {
+                // Clear
+                cache = S3FIFO::new(CACHE_SIZE, SMALL_RATIO);
+            }
+        }
+
+        cache.assert_invariants();
+        assert!(cache.len() <= cache.capacity());
+    }
+});
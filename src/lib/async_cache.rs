@@ -0,0 +1,206 @@
+use std::future::Future;
+use std::hash::Hash;
+
+use tokio::sync::{Mutex, MutexGuard, TryLockError};
+
+use crate::s3fifo::S3FIFO;
+
+///
+/// An `S3FIFO` shared between tasks, behind a `tokio::sync::Mutex`. Even
+/// a read updates frequencies, so every operation takes the lock; values
+/// are returned by clone. Share it with an `Arc`.
+///
+/// ```
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// use s3fifo::prelude::*;
+///
+/// let cache: AsyncS3FIFO<u32, String> = AsyncS3FIFO::new(S3FIFO::default());
+/// cache.put(1, "one".to_string()).await;
+/// assert_eq!(cache.get(&1).await.as_deref(), Some("one"));
+/// # });
+/// ```
+pub struct AsyncS3FIFO<K, V> {
+    cache: Mutex<S3FIFO<K, V>>,
+}
+
+impl<K, V> AsyncS3FIFO<K, V> {
+    pub fn new(cache: S3FIFO<K, V>) -> AsyncS3FIFO<K, V> {
+        AsyncS3FIFO { cache: Mutex::new(cache) }
+    }
+
+    /// Lock the cache for anything not wrapped here.
+    #[inline(always)]
+    pub async fn lock(&self) -> MutexGuard<'_, S3FIFO<K, V>> {
+        self.cache.lock().await
+    }
+
+    pub fn into_inner(self) -> S3FIFO<K, V> {
+        self.cache.into_inner()
+    }
+}
+
+impl<K, V> From<S3FIFO<K, V>> for AsyncS3FIFO<K, V> {
+    fn from(cache: S3FIFO<K, V>) -> Self {
+        AsyncS3FIFO::new(cache)
+    }
+}
+
+impl<K, V> AsyncS3FIFO<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    pub async fn get(&self, key: &K) -> Option<V> {
+        self.cache.lock().await.get(key).cloned()
+    }
+
+    /// See `S3FIFO::put`.
+    pub async fn put(&self, key: K, value: V) -> Option<V> {
+        self.cache.lock().await.put(key, value)
+    }
+
+    /// See `S3FIFO::take`.
+    pub async fn remove(&self, key: &K) -> Option<V> {
+        self.cache.lock().await.take(key)
+    }
+
+    /// Drop every entry, ghost keys included.
+    pub async fn clear(&self) {
+        self.cache.lock().await.drain_to_vec_with_priority();
+    }
+
+    ///
+    /// The cached value for `key`, or `loader(key)`'s, cached on the way
+    /// out. Double-checked: a hit only holds the lock for the lookup; on a
+    /// miss the lock is held across the load, and the key is checked again
+    /// first, so concurrent callers missing the same key run `loader` once.
+    /// Other keys wait for the load too.
+    pub async fn get_or_load<F, Fut>(&self, key: K, loader: F) -> V
+    where
+        F: FnOnce(K) -> Fut,
+        Fut: Future<Output = V>,
+    {
+        if let Some(value) = self.get(&key).await {
+            return value;
+        }
+
+        let mut cache = self.cache.lock().await;
+        if let Some(value) = cache.get(&key) {
+            return value.clone();
+        }
+        let value = loader(key.clone()).await;
+        cache.put(key, value.clone());
+        value
+    }
+
+    ///
+    /// `get` without waiting: fails if another task holds the lock.
+    pub fn try_get(&self, key: &K) -> Result<Option<V>, TryLockError> {
+        Ok(self.cache.try_lock()?.get(key).cloned())
+    }
+
+    ///
+    /// `put` without waiting. If another task holds the lock, the pair is
+    /// handed back untouched.
+    pub fn try_put(&self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        match self.cache.try_lock() {
+            Ok(mut cache) => Ok(cache.put(key, value)),
+            Err(_) => Err((key, value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_puts_are_all_readable() {
+        // Nothing is read before the end, so everything must fit in the
+        // small queue.
+        let cache: Arc<AsyncS3FIFO<usize, usize>> =
+            Arc::new(AsyncS3FIFO::new(S3FIFO::try_new(2000, 0.5).unwrap()));
+
+        let tasks: Vec<_> = (0..10)
+            .map(|task| {
+                let cache = Arc::clone(&cache);
+                tokio::spawn(async move {
+                    for i in 0..100 {
+                        let key = task * 100 + i;
+                        cache.put(key, key * 2).await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        for key in 0..1000 {
+            assert_eq!(cache.get(&key).await, Some(key * 2));
+        }
+        cache.lock().await.assert_invariants();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn get_or_load_runs_the_loader_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let cache: Arc<AsyncS3FIFO<usize, String>> =
+            Arc::new(AsyncS3FIFO::new(S3FIFO::try_new(10, 0.5).unwrap()));
+        let loads = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..10)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let loads = Arc::clone(&loads);
+                tokio::spawn(async move {
+                    cache.get_or_load(7, |key| async move {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        // Give the other tasks time to miss too.
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        format!("loaded {}", key)
+                    }).await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), "loaded 7");
+        }
+
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.get(&7).await.as_deref(), Some("loaded 7"));
+    }
+
+    #[tokio::test]
+    async fn remove_and_clear() {
+        let cache: AsyncS3FIFO<isize, isize> = AsyncS3FIFO::new(S3FIFO::try_new(10, 0.5).unwrap());
+        for i in 0..4 {
+            cache.put(i, i).await;
+        }
+
+        assert_eq!(cache.remove(&0).await, Some(0));
+        assert_eq!(cache.remove(&0).await, None);
+        assert_eq!(cache.lock().await.len(), 3);
+
+        cache.clear().await;
+        assert!(cache.lock().await.is_empty());
+        assert_eq!(cache.get(&1).await, None);
+    }
+
+    #[tokio::test]
+    async fn try_ops_fail_while_locked() {
+        let cache: AsyncS3FIFO<isize, isize> = AsyncS3FIFO::new(S3FIFO::try_new(10, 0.5).unwrap());
+        assert_eq!(cache.try_put(1, 10), Ok(None));
+        assert_eq!(cache.try_get(&1).unwrap(), Some(10));
+
+        let guard = cache.lock().await;
+        assert!(cache.try_get(&1).is_err());
+        assert_eq!(cache.try_put(2, 20), Err((2, 20)));
+        drop(guard);
+
+        assert_eq!(cache.try_get(&2).unwrap(), None);
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::{self, Read, Write};
+
+///
+/// Fixed binary encoding for keys and values, used by
+/// `S3FIFO::save_to_file` and `S3FIFO::load_from_file`. Integers are
+/// little-endian; `usize`/`isize` are widened to 64 bits so files are
+/// portable across platforms.
+pub trait BinaryCodec: Sized {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self>;
+}
+
+macro_rules! impl_int_codec {
+    ($($int:ty),*) => {$(
+        impl BinaryCodec for $int {
+            #[inline(always)]
+            fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                writer.write_all(&self.to_le_bytes())
+            }
+
+            #[inline(always)]
+            fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+                let mut bytes = [0; std::mem::size_of::<$int>()];
+                reader.read_exact(&mut bytes)?;
+                Ok(<$int>::from_le_bytes(bytes))
+            }
+        }
+    )*};
+}
+
+impl_int_codec!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl BinaryCodec for usize {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u64).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        usize::try_from(u64::decode(reader)?).map_err(invalid_data)
+    }
+}
+
+impl BinaryCodec for isize {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as i64).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        isize::try_from(i64::decode(reader)?).map_err(invalid_data)
+    }
+}
+
+impl BinaryCodec for bool {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u8).encode(writer)
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(invalid_data(format!("invalid bool byte {}", byte))),
+        }
+    }
+}
+
+/// Length-prefixed UTF-8.
+impl BinaryCodec for String {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.len().encode(writer)?;
+        writer.write_all(self.as_bytes())
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let len = usize::decode(reader)?;
+        let mut bytes = Vec::new();
+        reader.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(bytes).map_err(invalid_data)
+    }
+}
+
+/// Length-prefixed sequence of elements.
+impl<T: BinaryCodec> BinaryCodec for Vec<T> {
+    fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.len().encode(writer)?;
+        self.iter().try_for_each(|item| item.encode(writer))
+    }
+
+    fn decode<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let len = usize::decode(reader)?;
+        // Don't trust `len` for the allocation: the input may be corrupt.
+        let mut items = Vec::with_capacity(len.min(1024));
+        for _ in 0..len {
+            items.push(T::decode(reader)?);
+        }
+        Ok(items)
+    }
+}
+
+pub(crate) fn invalid_data<E>(err: E) -> io::Error
+where E: Into<Box<dyn std::error::Error + Send + Sync>>
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: BinaryCodec + PartialEq + std::fmt::Debug>(value: T) {
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes).unwrap();
+        assert_eq!(T::decode(&mut bytes.as_slice()).unwrap(), value);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(0xABu8);
+        round_trip(-12345i32);
+        round_trip(usize::MAX);
+        round_trip(isize::MIN);
+        round_trip(true);
+        round_trip(String::from("s3-fifo ✓"));
+        round_trip(vec![1u16, 2, 3]);
+        round_trip(Vec::<String>::new());
+    }
+
+    #[test]
+    fn truncated_input_fails() {
+        let mut bytes = Vec::new();
+        String::from("hello").encode(&mut bytes).unwrap();
+        bytes.pop();
+        let err = String::decode(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
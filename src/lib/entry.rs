@@ -0,0 +1,103 @@
+use std::hash::Hash;
+
+use super::s3fifo::S3FIFO;
+
+///
+/// A view into a single key of an `S3FIFO`, obtained from `S3FIFO::entry`.
+/// Inserting through a vacant entry evicts as needed, just like `S3FIFO::put`.
+pub enum Entry<'a, K, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+pub struct OccupiedEntry<'a, K, V> {
+    pub(crate) cache: &'a mut S3FIFO<K, V>,
+    pub(crate) key: K,
+}
+
+pub struct VacantEntry<'a, K, V> {
+    pub(crate) cache: &'a mut S3FIFO<K, V>,
+    pub(crate) key: K,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where 
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    #[inline(always)]
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut V
+    where F: FnOnce() -> V
+    {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where V: Default
+    {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V>
+where 
+    K: Eq + Hash,
+{
+    #[inline(always)]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn get(&self) -> &V {
+        self.cache.peek(&self.key).expect("occupied entry is cached")
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.cache.peek_mut(&self.key).expect("occupied entry is cached")
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        self.cache.peek_mut(&self.key).expect("occupied entry is cached")
+    }
+
+    /// Replace the value and return the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V>
+where 
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    #[inline(always)]
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    #[inline(always)]
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Insert `value` (evicting as needed) and return a reference to it.
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.cache.insert(self.key.clone(), value);
+        self.cache.peek_mut(&self.key).expect("key was just inserted")
+    }
+}
@@ -0,0 +1,61 @@
+use std::error::Error;
+use std::fmt::{self, Debug};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    /// A cache can't be sized from an empty collection.
+    EmptyMap,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::EmptyMap => write!(f, "cannot build a cache from an empty map"),
+        }
+    }
+}
+
+impl Error for ConversionError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The small queue ratio must lie strictly between 0 and 1.
+    InvalidRatio,
+    ZeroCapacity,
+    /// The ratio leaves the small queue without a single slot.
+    SmallQueueZeroCapacity,
+    /// The ratio leaves the main queue without a single slot.
+    MainQueueZeroCapacity,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidRatio => write!(f, "small queue ratio must be in (0, 1)"),
+            ConfigError::ZeroCapacity => write!(f, "cache capacity must be non-zero"),
+            ConfigError::SmallQueueZeroCapacity => {
+                write!(f, "small queue would have zero capacity")
+            }
+            ConfigError::MainQueueZeroCapacity => {
+                write!(f, "main queue would have zero capacity")
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+///
+/// A lookup that was expected to hit didn't. Carries the key that missed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheMiss<K> {
+    pub key: K,
+}
+
+impl<K: Debug> fmt::Display for CacheMiss<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key {:?} not found in cache", self.key)
+    }
+}
+
+impl<K: Debug> Error for CacheMiss<K> {}
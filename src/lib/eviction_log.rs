@@ -0,0 +1,67 @@
+use std::collections::VecDeque;
+
+///
+/// The most recently evicted keys, oldest first, for debugging eviction
+/// decisions. Attach one with `S3FIFO::with_eviction_log`. Only full
+/// evictions are recorded: not promotions, and not small queue entries
+/// whose key moved to the ghost queue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvictionLog<K> {
+    log: VecDeque<K>,
+    max_size: usize,
+}
+
+impl<K> EvictionLog<K> {
+    pub fn new(max_size: usize) -> EvictionLog<K> {
+        EvictionLog { log: VecDeque::with_capacity(max_size), max_size }
+    }
+
+    /// Append `key`, dropping the oldest one once `max_size` is reached.
+    pub(crate) fn record(&mut self, key: K) {
+        if self.max_size == 0 {
+            return;
+        }
+        if self.log.len() == self.max_size {
+            self.log.pop_front();
+        }
+        self.log.push_back(key);
+    }
+
+    /// Evicted keys, in eviction order.
+    pub fn iter(&self) -> impl Iterator<Item = &K> {
+        self.log.iter()
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.log.len()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.log.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_keys() {
+        let mut log = EvictionLog::new(3);
+        for key in 0..5 {
+            log.record(key);
+        }
+        assert_eq!(log.iter().copied().collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        let mut disabled = EvictionLog::new(0);
+        disabled.record(0);
+        assert!(disabled.is_empty());
+    }
+}
@@ -1,19 +1,93 @@
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
+use std::mem::size_of;
+use std::num::NonZeroUsize;
 use std::ops::Deref;
+use std::time::{Duration, Instant};
 
-use super::ring_buffer::RingBuffer;
+use super::ring_buffer::{FixedRingBuffer, RingBuffer};
 
-#[derive(Default, Clone)]
+/// Frequency counters saturate at this value.
+pub const MAX_FREQ: usize = 3;
+
+///
+/// How long an entry stays cached after it is inserted. Converts from a
+/// `Duration`, so `insert_with_ttl` takes either.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct TimeToLive(Duration);
+
+impl TimeToLive {
+    #[inline(always)]
+    pub const fn new(duration: Duration) -> TimeToLive {
+        TimeToLive(duration)
+    }
+
+    #[inline(always)]
+    pub const fn from_secs(secs: u64) -> TimeToLive {
+        TimeToLive(Duration::from_secs(secs))
+    }
+
+    #[inline(always)]
+    pub const fn duration(&self) -> Duration {
+        self.0
+    }
+
+    /// When an entry inserted at `now` expires.
+    #[inline(always)]
+    pub fn expires_at(&self, now: Instant) -> Instant {
+        now + self.0
+    }
+}
+
+impl From<Duration> for TimeToLive {
+    fn from(duration: Duration) -> Self {
+        TimeToLive(duration)
+    }
+}
+
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
 pub struct CacheMetadata {
     freq: usize,
+    expires_at: Option<Instant>,
+    /// Inserted ahead of demand rather than by a user access.
+    prefetched: bool,
 }
 
 impl CacheMetadata {
+    /// Metadata for an entry that has already been accessed `freq` times,
+    /// clamped to `MAX_FREQ`.
+    #[inline(always)]
+    pub fn with_freq(freq: usize) -> CacheMetadata {
+        CacheMetadata { freq: min(freq, MAX_FREQ), ..Default::default() }
+    }
+
+    /// Metadata for a fresh entry that expires `ttl` from now.
+    #[inline(always)]
+    pub fn with_ttl(ttl: impl Into<TimeToLive>) -> CacheMetadata {
+        let expires_at = ttl.into().expires_at(Instant::now());
+        CacheMetadata { expires_at: Some(expires_at), ..Default::default() }
+    }
+
+    /// Metadata for an entry loaded ahead of demand.
+    #[inline(always)]
+    pub fn prefetched() -> CacheMetadata {
+        CacheMetadata { prefetched: true, ..Default::default() }
+    }
+
+    #[inline(always)]
+    pub fn is_prefetched(&self) -> bool {
+        self.prefetched
+    }
+
+    #[inline(always)]
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Instant::now())
+    }
+
     #[inline(always)]
     pub fn inc_freq(&mut self) {
-        self.freq = min(self.freq + 1, 3);
+        self.freq = min(self.freq + 1, MAX_FREQ);
     }
 
     #[inline(always)]
@@ -39,16 +113,38 @@ impl<V> CacheObject<V> {
         self.meta.desc_freq();
     }
 
+    /// Overwrite the frequency, clamped to `MAX_FREQ`.
+    #[inline(always)]
+    pub fn set_freq(&mut self, freq: usize) {
+        self.meta.freq = min(freq, MAX_FREQ);
+    }
+
     #[inline(always)]
     pub fn set_value(&mut self, value: V) {
         self.value = value;
     }
 
+    /// Set the value and hand back the previous one.
+    #[inline(always)]
+    pub fn replace_value(&mut self, value: V) -> V {
+        std::mem::replace(&mut self.value, value)
+    }
+
     #[inline(always)]
     pub fn get_value(&self) -> &V {
         &self.value
     }
 
+    #[inline(always)]
+    pub fn into_value(self) -> V {
+        self.value
+    }
+
+    #[inline(always)]
+    pub fn get_value_mut(&mut self) -> &mut V {
+        &mut self.value
+    }
+
     #[inline(always)]
     pub fn get_value_copy(&self) -> V where V: Clone {
         self.value.clone()
@@ -78,6 +174,7 @@ impl<V> Deref for CacheObject<V> {
     }
 }
 
+#[derive(Clone)]
 pub struct FIFOCache<K, V> {
     rb: RingBuffer<K>,
     hashtable: HashMap<K, CacheObject<V>>,
@@ -92,24 +189,36 @@ where
     #[must_use]
     pub fn new(capacity: usize) -> FIFOCache<K, V> {
         FIFOCache { 
-            rb: RingBuffer::new(capacity), 
+            rb: RingBuffer::new(
+                NonZeroUsize::new(capacity).expect("FIFOCache capacity must be non-zero")
+            ), 
+            hashtable: HashMap::new()
+        }
+    }
+
+    /// Like `new`, but nothing is allocated until the first insert, for a
+    /// queue that may never be used.
+    pub(crate) fn unallocated(capacity: usize) -> FIFOCache<K, V> {
+        FIFOCache { 
+            rb: RingBuffer::unallocated(
+                NonZeroUsize::new(capacity).expect("FIFOCache capacity must be non-zero")
+            ), 
             hashtable: HashMap::new()
         }
     }
 
     ///
-    /// No-op if the key isn't present.
-    /// 
-    /// **DO NOT USE THIS FUNCTION FOR NOW**
-    pub fn update(&mut self, key: K, value: V) {
-        if self.hashtable.contains_key(&key) {
-            self.hashtable
-                .entry(key)
-                .and_modify(|obj| { 
-                    obj.set_value(value);
-                    // Should we manage metadat in this level???
-                    // obj.inc_freq()
-                });
+    /// Replace the value of `key`, e.g. for a background refresh. This is
+    /// not an access: the frequency and the entry's place in eviction
+    /// order are left untouched. Returns whether the key was found; no-op
+    /// if it wasn't.
+    pub fn update(&mut self, key: &K, value: V) -> bool {
+        match self.hashtable.get_mut(key) {
+            Some(obj) => {
+                obj.set_value(value);
+                true
+            }
+            None => false,
         }
     }
 }
@@ -127,6 +236,17 @@ where
         self.insert_with_meta(key, value, meta);
     }
 
+    pub fn insert_with_freq(&mut self, key: K, value: V, freq: usize) {
+        self.insert_with_meta(key, value, CacheMetadata::with_freq(freq));
+    }
+
+    ///
+    /// Insert an entry that `find` stops returning once `ttl` has passed.
+    /// Expired entries are dropped when looked up, or by `evict_expired`.
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: impl Into<TimeToLive>) {
+        self.insert_with_meta(key, value, CacheMetadata::with_ttl(ttl));
+    }
+
     pub fn insert_with_meta(&mut self, key: K, value: V, meta: CacheMetadata) {
         self.hashtable.insert(
             key.clone(), 
@@ -136,6 +256,30 @@ where
  
     }
 
+    /// Remove `key` and hand back its value. O(n) in the queue length.
+    pub fn take(&mut self, key: &K) -> Option<V> {
+        let obj = self.hashtable.remove(key)?;
+        if let Some(index) = self.rb.position_of(key) {
+            self.rb.remove(index);
+        }
+        Some(obj.value)
+    }
+
+    /// Move `key` to the back of the queue, as if it had just been
+    /// inserted. Returns whether it was found. O(n) in the queue length.
+    pub fn move_to_back(&mut self, key: &K) -> bool {
+        if !self.hashtable.contains_key(key) {
+            return false;
+        }
+        match self.rb.position_of(key).and_then(|index| self.rb.remove(index)) {
+            Some(key) => {
+                self.rb.push_back(key);
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn evict(&mut self) -> Option<(K, CacheObject<V>)> {
         let key = self.rb.pop_front();
         if let Some(key) = key {
@@ -144,6 +288,92 @@ where
             None
         }
     }
+
+    /// Remove every entry for which `predicate` holds and return them in
+    /// FIFO order. The rest keep their order. O(n).
+    pub fn extract_if(&mut self, mut predicate: impl FnMut(&K, &V) -> bool) -> Vec<(K, V)> {
+        self.drain_where(|key, obj| predicate(key, obj.get_value()))
+            .into_iter()
+            .map(|(key, obj)| (key, obj.into_value()))
+            .collect()
+    }
+
+    /// Like `extract_if`, but `predicate` sees the metadata too, e.g. the
+    /// frequency, and the entries come back whole.
+    pub fn drain_where(
+        &mut self, 
+        mut predicate: impl FnMut(&K, &CacheObject<V>) -> bool
+    ) -> Vec<(K, CacheObject<V>)> {
+        let mut drained = Vec::new();
+        for _ in 0..self.rb.len() {
+            let Some(key) = self.rb.pop_front() else { break };
+            let matched = self.hashtable
+                .get(&key)
+                .is_some_and(|obj| predicate(&key, obj));
+            if matched {
+                let obj = self.hashtable.remove(&key).expect("key was just found");
+                drained.push((key, obj));
+            } else {
+                self.rb.push_back(key);
+            }
+        }
+        drained
+    }
+
+    /// Like `evict`, but takes the most recently inserted entry.
+    pub fn evict_mru(&mut self) -> Option<(K, CacheObject<V>)> {
+        let key = self.rb.pop_back()?;
+        self.hashtable.remove_entry(&key)
+    }
+
+    /// Drop every expired entry. Returns how many were dropped.
+    pub fn evict_expired(&mut self) -> usize {
+        let expired: Vec<K> = self.hashtable
+            .iter()
+            .filter(|(_, obj)| obj.meta.is_expired())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            self.hashtable.remove(key);
+        }
+
+        // Also reclaims slots left behind by expired entries `find` dropped.
+        let mut index = 0;
+        while let Some(key) = self.rb.peek_at(index) {
+            if self.hashtable.contains_key(key) {
+                index += 1;
+            } else {
+                self.rb.remove(index);
+            }
+        }
+        expired.len()
+    }
+
+    ///
+    /// Bring the ring buffer and the hash table back in sync. `insert`
+    /// doesn't check for an existing key, so re-inserting one leaves a
+    /// second ring buffer slot behind, and an insert into a full queue
+    /// overwrites the oldest slot without dropping its hash table entry.
+    /// Only the newest slot of a duplicated key is kept, and hash table
+    /// entries without a slot are removed. Returns how many slots and
+    /// entries were dropped. O(n).
+    pub fn collect_garbage(&mut self) -> usize {
+        let before = self.rb.len() + self.hashtable.len();
+
+        let mut seen = HashSet::with_capacity(self.rb.len());
+        let mut keys = Vec::with_capacity(self.rb.len());
+        while let Some(key) = self.rb.pop_back() {
+            if seen.insert(key.clone()) {
+                keys.push(key);
+            }
+        }
+        for key in keys.into_iter().rev() {
+            self.rb.push_back(key);
+        }
+        self.hashtable.retain(|key, _| seen.contains(key));
+
+        before - self.rb.len() - self.hashtable.len()
+    }
 }
 
 impl<K, V> FIFOCache<K, V>
@@ -158,11 +388,33 @@ impl<K, V> FIFOCache<K, V>
     }
 
 
+    /// Drop `key` if it has expired, ring buffer slot included, so a
+    /// re-insert of the key can't be evicted through the stale slot.
+    /// O(n) in the queue length when it has.
+    #[inline(always)]
+    fn remove_if_expired(&mut self, key: &K) -> bool
+    where K: Eq + Hash + Clone
+    {
+        let expired = self.hashtable
+            .get(key)
+            .is_some_and(|obj| obj.meta.is_expired());
+        if expired {
+            self.hashtable.remove(key);
+            if let Some(index) = self.rb.position_of(key) {
+                self.rb.remove(index);
+            }
+        }
+        expired
+    }
+
     // Separate impl block more generic trait bound
     #[inline(always)]
     pub fn find(&mut self, key: &K) -> Option<&CacheObject<V>>
     where K: Eq + Hash + Clone
     {
+        if self.remove_if_expired(key) {
+            return None;
+        }
         self.inc_freq(key);
         self.hashtable.get(key)
     }
@@ -171,26 +423,384 @@ impl<K, V> FIFOCache<K, V>
     pub fn find_mut(&mut self, key: &K) -> Option<&mut CacheObject<V>> 
     where K: Eq + Hash + Clone
     {
+        if self.remove_if_expired(key) {
+            return None;
+        }
         self.inc_freq(key);
         self.hashtable.get_mut(key)
     }
 
+    /// Replace the value of `key` with `f(&old)`. Returns whether the key
+    /// was found. The frequency is left untouched.
+    pub fn update_if_present(&mut self, key: &K, f: impl FnOnce(&V) -> V) -> bool
+    where K: Eq + Hash
+    {
+        match self.hashtable.get_mut(key) {
+            Some(obj) => {
+                let value = f(obj.get_value());
+                obj.set_value(value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the value of `key` to `new_value` only if it currently equals
+    /// `expected`. Returns whether it was swapped. The frequency is left
+    /// untouched either way.
+    pub fn compare_and_swap(&mut self, key: &K, expected: &V, new_value: V) -> bool
+    where K: Eq + Hash, V: PartialEq
+    {
+        match self.hashtable.get_mut(key) {
+            Some(obj) if obj.get_value() == expected => {
+                obj.set_value(new_value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Set the frequency of `key` back to zero. Returns whether it was found.
+    pub fn reset_freq(&mut self, key: &K) -> bool
+    where K: Eq + Hash
+    {
+        self.hashtable
+            .get_mut(key)
+            .map(|obj| obj.set_freq(0))
+            .is_some()
+    }
+
+    /// Decrement the frequency of every entry.
+    pub fn age_frequencies(&mut self) {
+        for obj in self.hashtable.values_mut() {
+            obj.desc_freq();
+        }
+    }
+
+    /// Like `find`, but leaves the frequency untouched.
+    #[inline(always)]
+    pub fn peek(&self, key: &K) -> Option<&CacheObject<V>>
+    where K: Eq + Hash
+    {
+        self.hashtable.get(key)
+    }
+
+    /// Like `find_mut`, but leaves the frequency untouched.
+    #[inline(always)]
+    pub fn peek_mut(&mut self, key: &K) -> Option<&mut CacheObject<V>>
+    where K: Eq + Hash
+    {
+        self.hashtable.get_mut(key)
+    }
+
+    /// Mutable access to every entry, in no particular order.
+    pub(crate) fn entries_mut(&mut self) -> impl Iterator<Item = (&K, &mut CacheObject<V>)> {
+        self.hashtable.iter_mut()
+    }
+
+    /// Call `f` on every entry, in no particular order. Frequencies are
+    /// left untouched.
+    pub fn for_each_mut(&mut self, mut f: impl FnMut(&K, &mut V)) {
+        for (key, obj) in self.entries_mut() {
+            f(key, obj.get_value_mut());
+        }
+    }
+
     #[inline(always)]
     pub fn len(&self) -> usize {
         self.rb.len()
     }
 
     #[inline(always)]
-    pub fn empty(&self) -> bool {
+    pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Keys in FIFO order, oldest first.
+    pub fn keys(&self) -> impl Iterator<Item = &K>
+    where K: Eq + Hash
+    {
+        self.items().map(|(key, _)| key)
+    }
+
+    /// Values in FIFO order, oldest first.
+    pub fn values(&self) -> impl Iterator<Item = &V>
+    where K: Eq + Hash
+    {
+        self.items().map(|(_, value)| value)
+    }
+
+    /// Same as `keys`, for callers that want the order spelled out.
+    #[inline(always)]
+    pub fn keys_in_order(&self) -> impl Iterator<Item = &K>
+    where K: Eq + Hash
+    {
+        self.keys()
+    }
+
+    /// Same as `values`, for callers that want the order spelled out.
+    #[inline(always)]
+    pub fn values_in_order(&self) -> impl Iterator<Item = &V>
+    where K: Eq + Hash
+    {
+        self.values()
+    }
+
+    /// Key-value pairs in FIFO order, oldest first.
+    /// Unlike `find`, this does not touch the frequency counters.
+    pub fn items(&self) -> impl Iterator<Item = (&K, &V)>
+    where K: Eq + Hash
+    {
+        self.iter_in_order().map(|(key, obj)| (key, obj.get_value()))
+    }
+
+    /// The entry `index` positions from the front of the queue.
+    #[inline(always)]
+    pub fn item_at(&self, index: usize) -> Option<(&K, &V)>
+    where K: Eq + Hash
+    {
+        let key = self.rb.peek_at(index)?;
+        self.hashtable
+            .get_key_value(key)
+            .map(|(key, obj)| (key, obj.get_value()))
+    }
+
+    /// The oldest key whose value equals `value`. O(n), meant for debugging
+    /// and tests.
+    pub fn find_by_value(&self, value: &V) -> Option<&K>
+    where K: Eq + Hash, V: PartialEq
+    {
+        self.iter_in_order()
+            .find(|(_, obj)| obj.get_value() == value)
+            .map(|(key, _)| key)
+    }
+
+    /// The oldest cached key matching `predicate`. O(n).
+    pub fn find_by_key_pred(&self, predicate: impl Fn(&K) -> bool) -> Option<&K>
+    where K: Eq + Hash
+    {
+        self.iter_in_order()
+            .map(|(key, _)| key)
+            .find(|key| predicate(key))
+    }
+
+    /// Position of `key` from the front of the queue, 0 being the next to
+    /// be evicted. `None` if it isn't cached. O(n) in the queue length.
+    pub fn position_of(&self, key: &K) -> Option<usize>
+    where K: Eq + Hash
+    {
+        if !self.hashtable.contains_key(key) {
+            return None;
+        }
+        self.rb.position_of(key)
+    }
+
+    ///
+    /// Up to `n` cached keys on each side of `key` in FIFO order: older
+    /// ones (evicted first) oldest first, then newer ones. `None` if `key`
+    /// isn't cached. O(n) in the queue length.
+    pub fn neighbor_keys(&self, key: &K, n: usize) -> Option<(Vec<&K>, Vec<&K>)>
+    where K: Eq + Hash
+    {
+        let index = self.position_of(key)?;
+        // Expired entries may still sit in the ring buffer.
+        let cached = |key: &&K| self.hashtable.contains_key(*key);
+
+        let mut older: Vec<&K> = (0..index).rev()
+            .filter_map(|i| self.rb.peek_at(i))
+            .filter(cached)
+            .take(n)
+            .collect();
+        older.reverse();
+        let newer = (index + 1..self.rb.len())
+            .filter_map(|i| self.rb.peek_at(i))
+            .filter(cached)
+            .take(n)
+            .collect();
+        Some((older, newer))
+    }
+
+    /// Panics unless the ring buffer and the hash table hold exactly the
+    /// same keys. `name` identifies the queue in the message.
+    pub(crate) fn assert_invariants(&self, name: &str)
+    where K: Eq + Hash
+    {
+        assert_eq!(
+            self.hashtable.len(), self.rb.len(),
+            "{} queue: hash table has {} entries but ring buffer has {}",
+            name, self.hashtable.len(), self.rb.len()
+        );
+        let keys: HashSet<&K> = self.rb.iter().collect();
+        assert_eq!(keys.len(), self.rb.len(), "{} queue: duplicate key in ring buffer", name);
+        assert!(
+            keys.iter().all(|key| self.hashtable.contains_key(key)),
+            "{} queue: ring buffer key missing from hash table", name
+        );
+    }
+
+    /// Entries in FIFO order, i.e. the order `evict` would return them.
+    pub fn iter_in_order(&self) -> impl Iterator<Item = (&K, &CacheObject<V>)>
+    where K: Eq + Hash
+    {
+        self.rb.iter().filter_map(|key| self.hashtable.get_key_value(key))
+    }
 }
 
 impl<K, V> FIFOCache<K, V> {
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.rb.capacity()
+    }
+
     #[inline(always)]
     pub fn is_full(&self) -> bool {
         self.rb.is_full()
     }
+
+    /// Ring buffer slots currently allocated, see `shrink_to_fit`.
+    #[inline(always)]
+    pub(crate) fn allocated_slots(&self) -> usize {
+        self.rb.allocated()
+    }
+
+    /// Release spare hash table capacity and shrink the ring buffer to the
+    /// queue length. The queue capacity is unchanged: the ring buffer grows
+    /// back as entries are inserted.
+    pub fn shrink_to_fit(&mut self)
+    where K: Default + Eq + Hash
+    {
+        self.hashtable.shrink_to_fit();
+        self.rb.shrink_to_fit();
+    }
+
+    /// Release spare hash table capacity and move the ring buffer's
+    /// head back to the start of its storage.
+    pub fn compact(&mut self)
+    where K: Eq + Hash
+    {
+        self.rb.make_contiguous();
+        self.hashtable.shrink_to_fit();
+    }
+
+    /// Rough number of bytes allocated but not holding an entry.
+    pub fn memory_overhead_estimate(&self) -> usize {
+        let table_slack = self.hashtable.capacity() - self.hashtable.len();
+        let rb_slack = self.rb.allocated() - self.rb.len();
+        table_slack * size_of::<(K, CacheObject<V>)>() + rb_slack * size_of::<K>()
+    }
+}
+
+impl<K, V> FIFOCache<K, V>
+where 
+    K: Clone + Eq + Hash,
+    V: PartialEq,
+{
+    /// Same as `==`, but the frequency counters of every entry must match too.
+    pub(crate) fn eq_with_freq(&self, other: &Self) -> bool {
+        self == other && self.hashtable.iter().all(|(key, obj)| {
+            other.hashtable
+                .get(key)
+                .is_some_and(|other_obj| obj.meta == other_obj.meta)
+        })
+    }
+}
+
+///
+/// Two caches are equal if they hold the same key-value pairs in the same
+/// FIFO order. Frequency counters are ignored.
+impl<K, V> PartialEq for FIFOCache<K, V>
+where 
+    K: Clone + Eq + Hash,
+    V: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.hashtable.len() == other.hashtable.len()
+            && self.hashtable.iter().all(|(key, obj)| {
+                other.hashtable
+                    .get(key)
+                    .is_some_and(|other_obj| obj.value == other_obj.value)
+            })
+            && self.rb.get_values() == other.rb.get_values()
+    }
+}
+
+impl<K, V> Eq for FIFOCache<K, V>
+where 
+    K: Clone + Eq + Hash,
+    V: Eq,
+{}
+
+///
+/// `FIFOCache` with inline storage for `CAP` entries, for small caches that
+/// should live on the stack. Lookups scan the queue, O(`CAP`), which is
+/// cheap at the sizes this is meant for. Keys only need `Eq`.
+pub struct FixedFIFOCache<K, V, const CAP: usize> {
+    entries: FixedRingBuffer<(K, CacheObject<V>), CAP>,
+}
+
+impl<K, V, const CAP: usize> FixedFIFOCache<K, V, CAP>
+where K: Eq
+{
+    pub fn new() -> FixedFIFOCache<K, V, CAP> {
+        FixedFIFOCache { entries: FixedRingBuffer::new() }
+    }
+
+    /// Append an entry, dropping the oldest one if the queue is full.
+    /// The key must not be present already.
+    pub fn insert_with_meta(&mut self, key: K, value: V, meta: CacheMetadata) {
+        self.entries.push_back((key, CacheObject { value, meta }));
+    }
+
+    pub fn evict(&mut self) -> Option<(K, CacheObject<V>)> {
+        self.entries.pop_front()
+    }
+
+    /// Look up `key`, counting it as an access.
+    pub fn find_mut(&mut self, key: &K) -> Option<&mut CacheObject<V>> {
+        let obj = self.peek_mut(key)?;
+        obj.inc_freq();
+        Some(obj)
+    }
+
+    /// Like `find_mut`, but leaves the frequency untouched.
+    pub fn peek(&self, key: &K) -> Option<&CacheObject<V>> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, obj)| obj)
+    }
+
+    pub fn peek_mut(&mut self, key: &K) -> Option<&mut CacheObject<V>> {
+        self.entries.iter_mut().find(|(k, _)| k == key).map(|(_, obj)| obj)
+    }
+
+    /// Keys in FIFO order, oldest first.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn is_full(&self) -> bool {
+        self.entries.is_full()
+    }
+
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        CAP
+    }
+}
+
+impl<K: Eq, V, const CAP: usize> Default for FixedFIFOCache<K, V, CAP> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[cfg(test)]
@@ -238,6 +848,159 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[test]
+    fn peek() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        cache.insert(0, 0);
+        assert_eq!(cache.peek(&0).map(|obj| obj.get_freq()), Some(0));
+        assert!(cache.peek(&1).is_none());
+    }
+
+    #[test]
+    fn insert_with_freq() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        cache.insert_with_freq(0, 0, 2);
+        cache.insert_with_freq(1, 1, 100);
+        assert_eq!(cache.peek(&0).unwrap().get_freq(), 2);
+        assert_eq!(cache.peek(&1).unwrap().get_freq(), MAX_FREQ);
+    }
+
+    #[test]
+    fn update() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(3);
+        for i in 0..3 {
+            cache.insert(i, i);
+        }
+        cache.find(&0);
+
+        assert!(cache.update(&0, 10));
+        assert!(!cache.update(&3, 3));
+        assert_eq!(cache.peek(&0).map(|obj| **obj), Some(10));
+        assert_eq!(cache.peek(&0).unwrap().get_freq(), 1);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2]);
+
+        // Still the oldest entry.
+        assert_eq!(cache.evict().map(|(key, obj)| (key, *obj)), Some((0, 10)));
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        cache.insert(0, 1);
+        assert!(cache.compare_and_swap(&0, &1, 2));
+        assert!(!cache.compare_and_swap(&0, &1, 3));
+        assert!(!cache.compare_and_swap(&1, &0, 3));
+        assert_eq!(cache.peek(&0).map(|obj| **obj), Some(2));
+        assert_eq!(cache.peek(&0).unwrap().get_freq(), 0);
+    }
+
+    #[test]
+    fn update_if_present() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        cache.insert(0, 1);
+        assert!(cache.update_if_present(&0, |v| v + 1));
+        assert_eq!(cache.peek(&0).map(|obj| **obj), Some(2));
+        assert_eq!(cache.peek(&0).unwrap().get_freq(), 0);
+
+        assert!(!cache.update_if_present(&1, |_| unreachable!()));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn take() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        for i in 0..3 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.take(&1), Some(10));
+        assert_eq!(cache.take(&1), None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![0, 2]);
+    }
+
+    #[test]
+    fn find_by_value() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        for i in 0..4 {
+            cache.insert(i, i * 10);
+        }
+        for i in 0..4 {
+            assert_eq!(cache.find_by_value(&(i * 10)), Some(&i));
+        }
+        assert_eq!(cache.find_by_value(&5), None);
+
+        cache.insert(4, 20);
+        assert_eq!(cache.find_by_value(&20), Some(&2));
+    }
+
+    #[test]
+    fn find_by_key_pred() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(3);
+        for i in 0..4 {
+            cache.insert(i, i);
+            if i == 0 {
+                cache.evict();
+            }
+        }
+        assert_eq!(cache.find_by_key_pred(|key| key % 2 == 0), Some(&2));
+        assert_eq!(cache.find_by_key_pred(|key| *key > 10), None);
+    }
+
+    #[test]
+    fn for_each_mut() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        for i in 0..3 {
+            cache.insert(i, i);
+        }
+        cache.find(&1);
+        cache.for_each_mut(|key, value| *value += key * 10);
+        assert_eq!(cache.values().copied().collect::<Vec<_>>(), vec![0, 11, 22]);
+        assert_eq!(cache.peek(&1).unwrap().get_freq(), 1);
+    }
+
+    #[test]
+    fn drain_where() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        for i in 0..4 {
+            cache.insert_with_freq(i, i, i as usize % 2);
+        }
+        let drained: Vec<isize> = cache.drain_where(|_, obj| obj.get_freq() == 0)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(drained, vec![0, 2]);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![1, 3]);
+        cache.assert_invariants("test");
+    }
+
+    #[test]
+    fn collect_garbage() {
+        let mut cache: FIFOCache<isize, ()> = FIFOCache::new(3);
+        // Re-inserting 1 leaves a second slot for it.
+        cache.insert(1, ());
+        cache.insert(2, ());
+        cache.insert(1, ());
+        assert_eq!((cache.rb.len(), cache.hashtable.len()), (3, 2));
+
+        // The queue is full, so this overwrites the oldest slot but
+        // leaves the hash table entry of 2 behind.
+        cache.insert(3, ());
+        cache.insert(4, ());
+        assert_eq!(cache.rb.to_vec(), vec![1, 3, 4]);
+        assert_eq!(cache.hashtable.len(), 4);
+
+        assert_eq!(cache.collect_garbage(), 1);
+        cache.assert_invariants("test");
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![1, 3, 4]);
+
+        // Both at once: 1 is overwritten and 3 is duplicated.
+        cache.insert(3, ());
+        assert_eq!(cache.collect_garbage(), 2);
+        cache.assert_invariants("test");
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![4, 3]);
+        assert_eq!(cache.collect_garbage(), 0);
+    }
+
     #[test]
     fn full_cache() {
         let mut cache: FIFOCache<isize, isize> = FIFOCache::new(3);
@@ -246,6 +1009,7 @@ mod tests {
         cache.insert(2, 2);
 
         assert!(cache.is_full());
+        assert_eq!(cache.capacity(), 3);
     }
 
     #[test]
@@ -271,7 +1035,7 @@ mod tests {
 
         for i in 0..capacity {
             let value = cache.evict();
-            assert!(!value.is_none(), "value should be present.");
+            assert!(value.is_some(), "value should be present.");
 
             if let Some((key, obj)) = value {
                 assert_eq!(key, i);
@@ -279,4 +1043,224 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn views() {
+        let mut cache: FIFOCache<usize, usize> = FIFOCache::new(5);
+        for i in 0..3 {
+            cache.insert(i, i * 10);
+        }
+
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(cache.values().copied().collect::<Vec<_>>(), vec![0, 10, 20]);
+        assert_eq!(
+            cache.items().map(|(k, v)| (*k, *v)).collect::<Vec<_>>(),
+            vec![(0, 0), (1, 10), (2, 20)]
+        );
+
+        // Views never bump the frequency.
+        assert!(cache.hashtable.values().all(|obj| obj.get_freq() == 0));
+    }
+
+    #[test]
+    fn iter_in_order() {
+        let mut cache: FIFOCache<usize, usize> = FIFOCache::new(10);
+        for i in 0..10 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.keys_in_order().copied().collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+
+        // Wrap around: the head is no longer at the start of the storage.
+        for _ in 0..7 {
+            cache.evict();
+        }
+        for i in 10..14 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.keys_in_order().copied().collect::<Vec<_>>(), vec![7, 8, 9, 10, 11, 12, 13]);
+        assert_eq!(
+            cache.iter_in_order().map(|(k, obj)| (*k, *obj.get_value())).collect::<Vec<_>>(),
+            (7..14).map(|i| (i, i * 10)).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            cache.values_in_order().copied().collect::<Vec<_>>(),
+            (7..14).map(|i| i * 10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn compact() {
+        let mut cache: FIFOCache<usize, usize> = FIFOCache::new(64);
+        for i in 0..64 {
+            cache.insert(i, i);
+        }
+        for _ in 0..60 {
+            cache.evict();
+        }
+
+        let before = cache.memory_overhead_estimate();
+        cache.compact();
+        assert!(cache.memory_overhead_estimate() < before);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![60, 61, 62, 63]);
+    }
+
+    #[test]
+    fn item_at() {
+        let mut cache: FIFOCache<usize, usize> = FIFOCache::new(3);
+        for i in 0..3 {
+            cache.insert(i, i * 10);
+        }
+        cache.evict();
+        cache.insert(3, 30);
+        assert_eq!(cache.item_at(0), Some((&1, &10)));
+        assert_eq!(cache.item_at(2), Some((&3, &30)));
+        assert_eq!(cache.item_at(3), None);
+    }
+
+    #[test]
+    fn position_of() {
+        let mut cache: FIFOCache<usize, usize> = FIFOCache::new(3);
+        for i in 0..3 {
+            cache.insert(i, i);
+        }
+        cache.evict();
+        cache.insert(3, 3);
+        assert_eq!(cache.position_of(&1), Some(0));
+        assert_eq!(cache.position_of(&3), Some(2));
+        assert_eq!(cache.position_of(&0), None);
+    }
+
+    #[test]
+    fn eq_ignores_freq() {
+        let mut cache: FIFOCache<usize, usize> = FIFOCache::new(5);
+        cache.insert(0, 0);
+        cache.insert(1, 1);
+        let pristine = cache.clone();
+
+        cache.find(&0);
+        assert!(cache == pristine);
+        assert!(!cache.eq_with_freq(&pristine));
+    }
+
+    #[test]
+    fn ne_on_order() {
+        let mut a: FIFOCache<usize, usize> = FIFOCache::new(5);
+        a.insert(0, 0);
+        a.insert(1, 1);
+
+        let mut b: FIFOCache<usize, usize> = FIFOCache::new(5);
+        b.insert(1, 1);
+        b.insert(0, 0);
+
+        assert!(a != b);
+    }
+
+    #[test]
+    fn expired_entries_are_missing() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        cache.insert_with_ttl(0, 0, Duration::ZERO);
+        cache.insert_with_ttl(1, 1, TimeToLive::from_secs(3600));
+        cache.insert(2, 2);
+
+        assert!(cache.find(&0).is_none());
+        assert!(cache.find_mut(&0).is_none());
+        assert_eq!(cache.find(&1).map(|obj| obj.get_freq()), Some(1));
+        assert!(cache.find(&2).is_some());
+
+        // The lookup dropped the ring buffer slot too.
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.evict().map(|(key, _)| key), Some(1));
+    }
+
+    #[test]
+    fn reinsert_after_expiry() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        cache.insert_with_ttl(1, 1, Duration::ZERO);
+        assert!(cache.find(&1).is_none());
+
+        cache.insert(1, 100);
+        cache.insert(2, 2);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.evict().map(|(key, obj)| (key, *obj.get_value())), Some((1, 100)));
+        assert!(cache.peek(&1).is_none());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.keys().collect::<Vec<_>>(), vec![&2]);
+    }
+
+    #[test]
+    fn evict_expired() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(5);
+        cache.insert_with_ttl(0, 0, Duration::ZERO);
+        cache.insert(1, 1);
+        cache.insert_with_ttl(2, 2, Duration::ZERO);
+        cache.insert_with_ttl(3, 3, Duration::ZERO);
+        assert!(cache.find(&3).is_none());
+
+        assert_eq!(cache.evict_expired(), 2);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.keys().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(cache.evict_expired(), 0);
+    }
+
+    #[test]
+    fn move_to_back() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(3);
+        for i in 0..3 {
+            cache.insert(i, i);
+        }
+        assert!(cache.move_to_back(&0));
+        assert!(!cache.move_to_back(&5));
+        assert_eq!(cache.keys().collect::<Vec<_>>(), vec![&1, &2, &0]);
+        assert_eq!(cache.evict().map(|(key, _)| key), Some(1));
+    }
+
+    #[test]
+    fn evict_mru() {
+        let mut cache: FIFOCache<isize, isize> = FIFOCache::new(3);
+        for i in 0..3 {
+            cache.insert(i, i * 10);
+        }
+        assert_eq!(cache.evict_mru().map(|(key, obj)| (key, obj.into_value())), Some((2, 20)));
+        cache.insert(3, 30);
+        assert_eq!(cache.evict_mru().map(|(key, _)| key), Some(3));
+        assert_eq!(cache.evict().map(|(key, _)| key), Some(0));
+        assert_eq!(cache.evict_mru().map(|(key, _)| key), Some(1));
+        assert!(cache.evict_mru().is_none());
+    }
+
+    #[test]
+    fn extract_if() {
+        let mut cache: FIFOCache<usize, usize> = FIFOCache::new(6);
+        for i in 0..6 {
+            cache.insert(i, i * 10);
+        }
+        cache.evict();
+        cache.insert(6, 60);
+
+        assert_eq!(cache.extract_if(|k, _| k % 3 == 0), vec![(3, 30), (6, 60)]);
+        assert_eq!(cache.keys().copied().collect::<Vec<_>>(), vec![1, 2, 4, 5]);
+        assert_eq!(cache.len(), 4);
+        assert!(cache.extract_if(|_, _| false).is_empty());
+    }
+
+    #[test]
+    fn fixed_matches_heap() {
+        let mut fixed: FixedFIFOCache<isize, isize, 3> = FixedFIFOCache::new();
+        let mut heap: FIFOCache<isize, isize> = FIFOCache::new(3);
+        for i in 0..3 {
+            fixed.insert_with_meta(i, i * 10, CacheMetadata::default());
+            heap.insert(i, i * 10);
+        }
+        assert!(fixed.is_full());
+
+        assert_eq!(fixed.find_mut(&1).map(|obj| obj.get_freq()), Some(1));
+        assert_eq!(heap.find(&1).map(|obj| obj.get_freq()), Some(1));
+        assert!(fixed.find_mut(&7).is_none());
+        assert_eq!(fixed.keys().collect::<Vec<_>>(), heap.keys().collect::<Vec<_>>());
+
+        let (key, obj) = fixed.evict().unwrap();
+        assert_eq!((key, *obj), heap.evict().map(|(key, obj)| (key, *obj)).unwrap());
+        assert_eq!(fixed.len(), heap.len());
+        assert_eq!(fixed.peek(&2).map(|obj| **obj), Some(20));
+    }
 }
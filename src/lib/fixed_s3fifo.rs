@@ -0,0 +1,191 @@
+use super::fifo_cache::{CacheMetadata, FixedFIFOCache};
+use super::ring_buffer::FixedRingBuffer;
+
+///
+/// S3-FIFO with every queue stored inline: `SCAP` small, `MCAP` main and
+/// `GCAP` ghost entries. Nothing is heap-allocated, so a small cache can
+/// live on the stack. Lookups scan the queues, so keep the capacities
+/// small.
+///
+/// Eviction works as in `S3FIFO` with the default configuration and
+/// without the admission sketch. Extras such as TTLs, stats and
+/// strategies are not supported.
+pub struct FixedS3FIFO<K, V, const SCAP: usize, const MCAP: usize, const GCAP: usize> {
+    s_queue: FixedFIFOCache<K, V, SCAP>,
+    m_queue: FixedFIFOCache<K, V, MCAP>,
+    g_queue: FixedRingBuffer<K, GCAP>,
+}
+
+impl<K, V, const SCAP: usize, const MCAP: usize, const GCAP: usize>
+    FixedS3FIFO<K, V, SCAP, MCAP, GCAP>
+where K: Eq
+{
+    /// Promote entries read at least twice while in the small queue.
+    const FREQ_PROMOTE_THRESHOLD: usize = 1;
+
+    pub fn new() -> FixedS3FIFO<K, V, SCAP, MCAP, GCAP> {
+        FixedS3FIFO {
+            s_queue: FixedFIFOCache::new(),
+            m_queue: FixedFIFOCache::new(),
+            g_queue: FixedRingBuffer::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        if self.s_queue.peek(key).is_some() {
+            return self.s_queue.find_mut(key).map(|obj| obj.get_value());
+        }
+        self.m_queue.find_mut(key).map(|obj| obj.get_value())
+    }
+
+    /// Insert or update `key`. Returns the previous value if the key was
+    /// already cached.
+    pub fn put(&mut self, key: K, value: V) -> Option<V> {
+        if self.s_queue.peek(&key).is_some() {
+            return self.s_queue.find_mut(&key).map(|obj| obj.replace_value(value));
+        }
+        if let Some(obj) = self.m_queue.find_mut(&key) {
+            return Some(obj.replace_value(value));
+        }
+
+        while self.len() >= self.capacity() {
+            self.evict();
+        }
+        if self.g_queue.iter().any(|ghost| *ghost == key) {
+            self.push_main(key, value);
+        } else {
+            self.push_small(key, value);
+        }
+        None
+    }
+
+    /// Whether `key` is cached. Not counted as an access.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.s_queue.peek(key).is_some() || self.m_queue.peek(key).is_some()
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.s_queue.len() + self.m_queue.len()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        SCAP + MCAP
+    }
+
+    fn push_small(&mut self, key: K, value: V) {
+        if self.s_queue.is_full() { self.evict_s() }
+        self.s_queue.insert_with_meta(key, value, CacheMetadata::default());
+    }
+
+    fn push_main(&mut self, key: K, value: V) {
+        if self.m_queue.is_full() { self.evict_m() }
+        self.m_queue.insert_with_meta(key, value, CacheMetadata::default());
+    }
+
+    fn evict(&mut self) {
+        if self.s_queue.is_full() {
+            self.evict_s();
+        }
+        if self.m_queue.is_full() {
+            self.evict_m();
+        }
+    }
+
+    /// Same as `S3FIFO::evict_s`: promote hot entries, evict the first cold
+    /// one into the ghost queue, and give up after one pass.
+    fn evict_s(&mut self) {
+        for _ in 0..self.s_queue.len() {
+            let Some((key, obj)) = self.s_queue.evict() else { return };
+            if obj.get_freq() > Self::FREQ_PROMOTE_THRESHOLD {
+                if self.m_queue.is_full() { self.evict_m() }
+                self.m_queue.insert_with_meta(key, obj.into_value(), CacheMetadata::default());
+            } else {
+                if !self.g_queue.iter().any(|ghost| *ghost == key) {
+                    self.g_queue.push_back(key);
+                }
+                return;
+            }
+        }
+    }
+
+    /// Same as `S3FIFO::evict_m`: reinsert entries with a non-zero frequency
+    /// (decremented), evict the first one at 0.
+    fn evict_m(&mut self) {
+        while let Some((key, obj)) = self.m_queue.evict() {
+            if obj.get_freq() == 0 {
+                return;
+            }
+            let mut meta = obj.get_meta_copy();
+            meta.desc_freq();
+            self.m_queue.insert_with_meta(key, obj.into_value(), meta);
+        }
+    }
+}
+
+impl<K, V, const SCAP: usize, const MCAP: usize, const GCAP: usize> Default
+    for FixedS3FIFO<K, V, SCAP, MCAP, GCAP>
+where K: Eq
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FixedS3FIFO;
+
+    // The admission sketch can turn ghost hits away, which the fixed
+    // variant doesn't model.
+    #[cfg(not(feature = "admission_sketch"))]
+    #[test]
+    fn matches_heap_variant() {
+        use crate::s3fifo::S3FIFO;
+        use crate::test_utils::XorShift;
+
+        const KEY_SPACE: usize = 16;
+
+        for seed in 0..200 {
+            let mut rng = XorShift::new(seed);
+            let mut fixed: FixedS3FIFO<usize, usize, 2, 6, KEY_SPACE> = FixedS3FIFO::new();
+            // The ghost queue fits every key, so both variants remember
+            // the same evicted keys.
+            let mut heap: S3FIFO<usize, usize> = S3FIFO::new_with_explicit_sizes(2, 6, KEY_SPACE);
+
+            for step in 0..300 {
+                let key = rng.below(KEY_SPACE);
+                if rng.below(3) == 0 {
+                    assert_eq!(fixed.put(key, step), heap.put(key, step));
+                } else {
+                    assert_eq!(fixed.get(&key), heap.get(&key), "seed {} step {}", seed, step);
+                }
+                assert_eq!(fixed.len(), heap.len());
+            }
+        }
+    }
+
+    #[test]
+    fn basic_operations() {
+        let mut cache: FixedS3FIFO<&str, i32, 1, 2, 2> = FixedS3FIFO::default();
+        assert!(cache.is_empty());
+        assert_eq!(cache.capacity(), 3);
+
+        assert_eq!(cache.put("a", 1), None);
+        assert_eq!(cache.put("a", 2), Some(1));
+        assert_eq!(cache.get(&"a"), Some(&2));
+        assert!(cache.contains_key(&"a"));
+
+        for (i, key) in ["b", "c", "d"].into_iter().enumerate() {
+            cache.put(key, i as i32);
+        }
+        assert!(cache.len() <= cache.capacity());
+        assert_eq!(cache.get(&"d"), Some(&2));
+    }
+}
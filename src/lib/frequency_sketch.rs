@@ -0,0 +1,124 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+const DEPTH: usize = 4;
+
+///
+/// Count-min sketch estimating how often keys were seen, in a fixed amount
+/// of memory regardless of how many distinct keys there are. Estimates
+/// never undercount, but may overcount when keys collide.
+///
+/// Counters are halved every `10 * width` increments so that old
+/// popularity fades (TinyLFU-style aging).
+#[derive(Clone, Debug)]
+pub struct FrequencySketch<K> {
+    table: Box<[[u32; DEPTH]]>,
+    mask: usize,
+    additions: usize,
+    sample_size: usize,
+    _key: PhantomData<fn(&K)>,
+}
+
+impl<K: Hash> FrequencySketch<K> {
+    /// A sketch with at least `width` counters per row, rounded up to a
+    /// power of two.
+    pub fn new(width: usize) -> FrequencySketch<K> {
+        let width = width.max(16).next_power_of_two();
+        FrequencySketch {
+            table: vec![[0; DEPTH]; width].into_boxed_slice(),
+            mask: width - 1,
+            additions: 0,
+            sample_size: width * 10,
+            _key: PhantomData,
+        }
+    }
+
+    pub fn increment(&mut self, key: &K) {
+        let hash = Self::hash(key);
+        for row in 0..DEPTH {
+            let index = self.index(hash, row);
+            let counter = &mut self.table[index][row];
+            *counter = counter.saturating_add(1);
+        }
+
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            self.reset_all();
+        }
+    }
+
+    /// Upper bound on how often `key` was incremented since it was last aged.
+    pub fn estimate(&self, key: &K) -> usize {
+        let hash = Self::hash(key);
+        (0..DEPTH)
+            .map(|row| self.table[self.index(hash, row)][row])
+            .min()
+            .unwrap_or(0) as usize
+    }
+
+    /// Halve every counter. Runs automatically as increments accumulate.
+    pub fn reset_all(&mut self) {
+        for counters in self.table.iter_mut() {
+            for counter in counters.iter_mut() {
+                *counter /= 2;
+            }
+        }
+        self.additions /= 2;
+    }
+
+    #[inline(always)]
+    fn hash(key: &K) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Column of `row` for a key hash. Each row mixes the hash differently.
+    #[inline(always)]
+    fn index(&self, hash: u64, row: usize) -> usize {
+        let mixed = hash
+            .wrapping_add(row as u64)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        ((mixed ^ (mixed >> 32)) as usize) & self.mask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_never_undercounts() {
+        let mut sketch = FrequencySketch::new(64);
+        for key in 0..32usize {
+            for _ in 0..key % 5 {
+                sketch.increment(&key);
+            }
+        }
+        for key in 0..32usize {
+            assert!(sketch.estimate(&key) >= key % 5);
+        }
+        assert_eq!(sketch.estimate(&1000), 0);
+    }
+
+    #[test]
+    fn reset_all_halves() {
+        let mut sketch = FrequencySketch::new(64);
+        for _ in 0..6 {
+            sketch.increment(&"hot");
+        }
+        assert_eq!(sketch.estimate(&"hot"), 6);
+        sketch.reset_all();
+        assert_eq!(sketch.estimate(&"hot"), 3);
+    }
+
+    #[test]
+    fn ages_automatically() {
+        let mut sketch = FrequencySketch::new(16);
+        for _ in 0..160 {
+            sketch.increment(&0);
+        }
+        assert!(sketch.estimate(&0) < 160);
+    }
+}
@@ -1,9 +1,41 @@
 pub mod ring_buffer;
 pub mod fifo_cache;
 pub mod s3fifo;
+pub mod entry;
+pub mod error;
+pub mod stats;
+pub mod frequency_sketch;
+pub mod codec;
+pub mod policy;
+pub mod eviction_log;
+pub mod fixed_s3fifo;
+#[cfg(feature = "async")]
+pub mod async_cache;
+#[cfg(feature = "tower")]
+pub mod tower_middleware;
+
+#[cfg(test)]
+mod test_utils;
 
 pub mod prelude {
-    pub use super::ring_buffer::RingBuffer;
-    pub use super::fifo_cache::FIFOCache;
-    pub use super::s3fifo::S3FIFO;
+    pub use super::ring_buffer::{FixedRingBuffer, RingBuffer};
+    pub use super::fifo_cache::{FIFOCache, FixedFIFOCache, TimeToLive, MAX_FREQ};
+    pub use super::s3fifo::{
+        BatchResult, CacheDiff, CacheOp, CacheQueryResult, EvictionStrategy, ReadOnlyS3FIFO, S3FIFO,
+        SegmentInfo, SharedS3FIFO,
+    };
+    pub use super::entry::{Entry, OccupiedEntry, VacantEntry};
+    pub use super::error::{CacheMiss, ConfigError, ConversionError};
+    pub use super::stats::CacheStats;
+    pub use super::frequency_sketch::FrequencySketch;
+    pub use super::codec::BinaryCodec;
+    pub use super::policy::{CachePolicy, DefaultS3FIFOPolicy, StrictPolicy};
+    pub use super::eviction_log::EvictionLog;
+    pub use super::fixed_s3fifo::FixedS3FIFO;
+    #[cfg(feature = "async")]
+    pub use super::async_cache::AsyncS3FIFO;
+    #[cfg(feature = "tower")]
+    pub use super::tower_middleware::{
+        CachedResponse, KeyExtractor, ResponseCache, S3FIFOLayer, S3FIFOService,
+    };
 }
\ No newline at end of file
@@ -0,0 +1,52 @@
+use super::fifo_cache::MAX_FREQ;
+
+///
+/// Decides when an entry moves between queues, see `S3FIFO::with_policy`.
+pub trait CachePolicy: Send + Sync {
+    /// Whether a small queue entry with frequency `freq` is promoted to
+    /// the main queue instead of evicted. `threshold` is the cache's
+    /// `freq_promote_threshold`.
+    fn should_promote(&self, freq: usize, threshold: usize) -> bool;
+
+    /// Whether a main queue entry with frequency `freq` is evicted instead
+    /// of reinserted with its frequency decremented.
+    fn should_evict(&self, freq: usize) -> bool;
+}
+
+///
+/// The policy from the S3-FIFO paper: promote entries read more than
+/// `threshold` times, evict main queue entries once their frequency has
+/// dropped to 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DefaultS3FIFOPolicy;
+
+impl CachePolicy for DefaultS3FIFOPolicy {
+    #[inline(always)]
+    fn should_promote(&self, freq: usize, threshold: usize) -> bool {
+        freq > threshold
+    }
+
+    #[inline(always)]
+    fn should_evict(&self, freq: usize) -> bool {
+        freq == 0
+    }
+}
+
+///
+/// Only promotes entries whose frequency saturated at `MAX_FREQ`,
+/// regardless of the cache's threshold, keeping the main queue for the
+/// hottest keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StrictPolicy;
+
+impl CachePolicy for StrictPolicy {
+    #[inline(always)]
+    fn should_promote(&self, freq: usize, _threshold: usize) -> bool {
+        freq >= MAX_FREQ
+    }
+
+    #[inline(always)]
+    fn should_evict(&self, freq: usize) -> bool {
+        freq == 0
+    }
+}
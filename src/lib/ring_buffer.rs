@@ -1,11 +1,19 @@
 use std::fmt::{self, Debug};
+use std::mem::MaybeUninit;
+use std::num::NonZeroUsize;
+use std::ops::{Bound, RangeBounds};
+use super::error::ConfigError;
 
 static DEFAULT_RINGBUF_SIZE: usize = 100;
 
 ///
 /// TODO: thread-safe
+#[derive(Clone)]
 pub struct RingBuffer<T> {
-    buffer: Box<[T]>,
+    // May hold fewer slots than `capacity` after `shrink_to_fit`, or none
+    // at all if built `unallocated`; pushes grow it back. Indices wrap
+    // around `buffer.len()`.
+    buffer: Vec<T>,
     capacity: usize,
     head: usize,
     tail: usize,
@@ -13,15 +21,23 @@ pub struct RingBuffer<T> {
 }
 
 impl<T> RingBuffer<T> {
+    ///
+    /// A buffer holding up to `capacity` items. A zero capacity is ruled
+    /// out by the type:
+    ///
+    /// ```compile_fail
+    /// use s3fifo::prelude::*;
+    ///
+    /// let rb = RingBuffer::<usize>::new(0usize);
+    /// ```
     #[inline]
     #[must_use]
-    pub fn new(capacity: usize) -> RingBuffer<T> 
+    pub fn new(capacity: NonZeroUsize) -> RingBuffer<T> 
     where T: Default + Clone 
     {
-        assert!(capacity != 0);
-
+        let capacity = capacity.get();
         RingBuffer { 
-            buffer: vec![T::default(); capacity].into_boxed_slice(), 
+            buffer: vec![T::default(); capacity], 
             capacity, 
             head: 0, 
             tail: 0, 
@@ -29,7 +45,34 @@ impl<T> RingBuffer<T> {
         }
     }
 
+    /// Like `new`, but no slot is allocated until the first push, for
+    /// buffers that may never be used.
+    pub(crate) fn unallocated(capacity: NonZeroUsize) -> RingBuffer<T> {
+        RingBuffer {
+            buffer: Vec::new(),
+            capacity: capacity.get(),
+            head: 0,
+            tail: 0,
+            size: 0
+        }
+    }
+
+    /// A full buffer holding `items` in order, with capacity `items.len()`.
+    /// `ConfigError::ZeroCapacity` if `items` is empty.
+    pub fn from_slice(items: &[T]) -> Result<RingBuffer<T>, ConfigError>
+    where T: Clone
+    {
+        RingBuffer::try_from(items.to_vec())
+    }
+
     pub fn push_front(&mut self, value: T) {
+        if self.needs_slot() {
+            // Full storage: the new slot goes right before the head.
+            self.buffer.insert(self.head, value);
+            self.size += 1;
+            self.tail = self.head;
+            return;
+        }
         self.head = self.index_backword(self.head);
         self.buffer[self.head] = value;
 
@@ -44,6 +87,18 @@ impl<T> RingBuffer<T> {
     }
 
     pub fn push_back(&mut self, value: T) {
+        if self.needs_slot() {
+            // Full storage: the new slot goes right after the last element,
+            // which sits just before `tail`.
+            let index = if self.tail == 0 { self.buffer.len() } else { self.tail };
+            self.buffer.insert(index, value);
+            if self.tail != 0 {
+                self.head += 1;
+            }
+            self.size += 1;
+            self.tail = (index + 1) % self.buffer.len();
+            return;
+        }
         self.buffer[self.tail] = value;
         self.tail = self.index_forward(self.tail);
 
@@ -62,11 +117,99 @@ impl<T> RingBuffer<T> {
         self.size
     }
 
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Rotate the backing storage so the head sits at index 0.
+    pub fn make_contiguous(&mut self) {
+        self.buffer.rotate_left(self.head);
+        self.head = 0;
+        self.tail = self.size % self.buffer.len().max(1);
+    }
+
+    ///
+    /// Reallocate the storage to hold just the current elements (at least
+    /// one slot), freeing unused slots and whatever popped elements still
+    /// own there. The capacity doesn't change: pushes grow the storage
+    /// back as needed.
+    pub fn shrink_to_fit(&mut self)
+    where T: Default
+    {
+        self.make_contiguous();
+        self.buffer.truncate(self.size.max(1));
+        if self.size == 0 {
+            self.buffer[0] = T::default();
+        }
+        self.buffer.shrink_to_fit();
+        self.tail = self.size % self.buffer.len();
+    }
+
+    /// Number of slots currently allocated, at most `capacity`.
+    #[inline(always)]
+    pub(crate) fn allocated(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Whether a push needs a new slot: the storage is full but the
+    /// buffer isn't.
+    #[inline(always)]
+    fn needs_slot(&self) -> bool {
+        self.size == self.buffer.len() && self.size < self.capacity
+    }
+
+    /// The element `index` positions from the head.
+    #[inline(always)]
+    pub fn peek_at(&self, index: usize) -> Option<&T> {
+        if index < self.size {
+            Some(&self.buffer[(self.head + index) % self.buffer.len()])
+        } else {
+            None
+        }
+    }
+
+    /// Index from the head of the first element equal to `value`. O(n).
+    pub fn position_of(&self, value: &T) -> Option<usize>
+    where T: PartialEq
+    {
+        self.iter().position(|item| item == value)
+    }
+
+    /// Iterate over the elements from head to tail.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.size).map(move |i| &self.buffer[(self.head + i) % self.buffer.len()])
+    }
+
     #[inline(always)]
     pub fn is_full(&self) -> bool {
         self.size == self.capacity
     }
 
+    ///
+    /// Discard the `n` oldest elements by moving the head, without
+    /// touching the slots. Clamped to `len`. Test-only: in a `FIFOCache`
+    /// this would leave the hash table behind.
+    #[cfg(test)]
+    pub(crate) fn rotate_left(&mut self, n: usize) {
+        let n = n.min(self.size);
+        self.head = (self.head + n) % self.buffer.len();
+        self.size -= n;
+    }
+
+    /// Like `rotate_left`, but discards the `n` newest elements.
+    #[cfg(test)]
+    pub(crate) fn rotate_right(&mut self, n: usize) {
+        let n = n.min(self.size);
+        self.tail = (self.tail + self.buffer.len() - n) % self.buffer.len();
+        self.size -= n;
+    }
+
 }
 
 impl<T> RingBuffer<T>
@@ -95,6 +238,82 @@ where T: Clone
         }
     }
 
+    /// Remove the element `index` positions from the head, shifting the
+    /// newer elements forward. O(n).
+    pub fn remove(&mut self, index: usize) -> Option<T>
+    {
+        if index >= self.size {
+            return None;
+        }
+
+        let mut cur = self.get_index(self.head, index as isize);
+        for _ in index..self.size - 1 {
+            let next = self.index_forward(cur);
+            self.buffer.swap(cur, next);
+            cur = next;
+        }
+        self.tail = self.index_backword(self.tail);
+        self.size -= 1;
+        Some(self.buffer[self.tail].clone())
+    }
+
+    ///
+    /// Push every item of `items` to the back, in order. Unlike
+    /// `push_back`, nothing is overwritten: if the items don't fit, the
+    /// buffer first grows to exactly `len + items.len()`, in one
+    /// reallocation.
+    pub fn extend_from_slice(&mut self, items: &[T]) {
+        let needed = self.size + items.len();
+        if needed > self.capacity {
+            self.make_contiguous();
+            let mut buffer = std::mem::take(&mut self.buffer);
+            buffer.truncate(self.size);
+            buffer.extend_from_slice(items);
+            *self = RingBuffer::full(buffer);
+            return;
+        }
+        for item in items {
+            self.push_back(item.clone());
+        }
+    }
+
+    ///
+    /// Remove the elements in `range`, counted from the head, and return
+    /// them in order. The remaining elements keep their FIFO order. O(n).
+    /// Panics if the range is out of bounds or decreasing, like
+    /// `Vec::drain`.
+    pub fn drain<R>(&mut self, range: R) -> impl Iterator<Item = T>
+    where R: RangeBounds<usize>
+    {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.size,
+        };
+        assert!(start <= end && end <= self.size, "drain range {}..{} out of bounds", start, end);
+
+        let drained: Vec<T> = (start..end)
+            .map(|i| self.buffer[self.get_index(self.head, i as isize)].clone())
+            .collect();
+        let count = end - start;
+        if count == 0 {
+            return drained.into_iter();
+        }
+        for i in start..self.size - count {
+            let to = self.get_index(self.head, i as isize);
+            let from = self.get_index(self.head, (i + count) as isize);
+            self.buffer.swap(to, from);
+        }
+        self.tail = self.get_index(self.tail, -(count as isize));
+        self.size -= count;
+        drained.into_iter()
+    }
+
     pub fn peak_front(&self) -> Option<T>
     where T: Clone 
     {
@@ -122,23 +341,43 @@ where T: Clone
 impl<T> RingBuffer<T> {
     #[inline(always)]
     fn get_index(&self, mut orig_index: usize, offset: isize) -> usize {
-        orig_index %= self.capacity;
-        let offset = offset.rem_euclid(self.capacity as isize) as usize;
-        (orig_index + offset) % self.capacity
+        let slots = self.buffer.len();
+        orig_index %= slots;
+        let offset = offset.rem_euclid(slots as isize) as usize;
+        (orig_index + offset) % slots
     }
 
-    /// Get the index next to the `orig_index`, wrapped by the `self.capacity`.
+    /// Get the index next to the `orig_index`, wrapped by the number of slots.
     #[inline(always)]
     fn index_forward(&self, orig_index: usize) -> usize {
         self.get_index(orig_index, 1)
     }
 
-    /// Get the index before the `orig_index`, wrapped by the `self.capacity`.
+    /// Get the index before the `orig_index`, wrapped by the number of slots.
     #[inline(always)]
     fn index_backword(&self, orig_index: usize) -> usize {
         self.get_index(orig_index, -1)
     }
 
+    /// A full buffer over `items`, which must not be empty.
+    fn full(items: Vec<T>) -> RingBuffer<T> {
+        let capacity = items.len();
+        RingBuffer {
+            buffer: items,
+            capacity,
+            head: 0,
+            tail: 0,
+            size: capacity,
+        }
+    }
+
+    /// The elements from head to tail.
+    pub fn to_vec(&self) -> Vec<T>
+    where T: Clone
+    {
+        self.iter().cloned().collect()
+    }
+
     pub fn get_values(&self) -> Vec<T>
     where T: Clone 
     {
@@ -176,24 +415,146 @@ where T: Clone + Debug
     }
 }
 
+///
+/// A full buffer with the vector's elements in order. An empty vector
+/// would make a zero-capacity buffer, which `new` rules out too.
+impl<T> TryFrom<Vec<T>> for RingBuffer<T> {
+    type Error = ConfigError;
+
+    fn try_from(items: Vec<T>) -> Result<Self, ConfigError> {
+        if items.is_empty() {
+            return Err(ConfigError::ZeroCapacity);
+        }
+        Ok(RingBuffer::full(items))
+    }
+}
+
+/// The elements from head to tail.
+impl<T> From<RingBuffer<T>> for Vec<T> {
+    fn from(mut rb: RingBuffer<T>) -> Self {
+        rb.make_contiguous();
+        let mut items = rb.buffer;
+        items.truncate(rb.size);
+        items
+    }
+}
+
 impl<T> Default for RingBuffer<T>
 where T: Default + Clone
 {
     #[inline]
-    #[must_use]
     fn default() -> Self
     {
-        RingBuffer::new(DEFAULT_RINGBUF_SIZE)
+        RingBuffer::new(NonZeroUsize::new(DEFAULT_RINGBUF_SIZE).unwrap())
     } 
 }
 
+///
+/// A ring buffer with its storage inline, so it can live on the stack.
+/// Unlike `RingBuffer`, elements are moved out rather than cloned, and
+/// pushing onto a full buffer hands back the element it displaces.
+pub struct FixedRingBuffer<T, const N: usize> {
+    buffer: [MaybeUninit<T>; N],
+    head: usize,
+    tail: usize,
+    len: usize,
+}
+
+impl<T, const N: usize> FixedRingBuffer<T, N> {
+    const NON_ZERO: () = assert!(N > 0, "FixedRingBuffer needs a non-zero capacity");
+
+    pub fn new() -> FixedRingBuffer<T, N> {
+        let () = Self::NON_ZERO;
+        FixedRingBuffer {
+            buffer: [const { MaybeUninit::uninit() }; N],
+            head: 0,
+            tail: 0,
+            len: 0,
+        }
+    }
+
+    /// Append `value`, returning the oldest element if it had to make room.
+    pub fn push_back(&mut self, value: T) -> Option<T> {
+        let displaced = if self.is_full() { self.pop_front() } else { None };
+        self.buffer[self.tail].write(value);
+        self.tail = (self.tail + 1) % N;
+        self.len += 1;
+        displaced
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        // SAFETY: the `len` slots from `head` are initialized, and `head`
+        // moves past this one so it's never read again.
+        let value = unsafe { self.buffer[self.head].assume_init_read() };
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn peek_front(&self) -> Option<&T> {
+        self.iter().next()
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline(always)]
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Iterate over the elements from head to tail.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        // SAFETY: the `len` slots from `head` are initialized.
+        (0..self.len).map(move |i| unsafe { self.buffer[(self.head + i) % N].assume_init_ref() })
+    }
+
+    /// Like `iter`, with mutable access.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        let (wrapped, from_head) = self.buffer.split_at_mut(self.head);
+        let first = self.len.min(from_head.len());
+        let rest = self.len - first;
+        from_head[..first].iter_mut()
+            .chain(wrapped[..rest].iter_mut())
+            // SAFETY: these are exactly the `len` slots from `head`.
+            .map(|slot| unsafe { slot.assume_init_mut() })
+    }
+}
+
+impl<T, const N: usize> Default for FixedRingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for FixedRingBuffer<T, N> {
+    fn drop(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn init() {
-        let rb = RingBuffer::<usize>::new(5);
+        let rb = RingBuffer::<usize>::new(NonZeroUsize::new(5).unwrap());
         assert_eq!(rb.capacity, 5);
         assert_eq!(rb.head, 0);
         assert_eq!(rb.tail, 0);
@@ -280,7 +641,7 @@ mod tests {
 
     #[test]
     fn over_push_back() {
-        let mut rb = RingBuffer::<usize>::new(3);
+        let mut rb = RingBuffer::<usize>::new(NonZeroUsize::new(3).unwrap());
         rb.push_back(0);
         rb.push_back(1);
         rb.push_back(2);
@@ -352,7 +713,7 @@ mod tests {
         for i in 0..DEFAULT_RINGBUF_SIZE {
             rb.push_back(i);
         }
-        assert_eq!(rb.is_full(), true);
+        assert!(rb.is_full());
     }
 
     #[test]
@@ -368,4 +729,299 @@ mod tests {
             assert_eq!(rb.len(), DEFAULT_RINGBUF_SIZE);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn iter() {
+        let mut rb = RingBuffer::<usize>::new(NonZeroUsize::new(3).unwrap());
+        assert_eq!(rb.iter().count(), 0);
+
+        for i in 0..5 {
+            rb.push_back(i);
+        }
+        let result: Vec<usize> = rb.iter().copied().collect();
+        assert_eq!(result, rb.get_values());
+        assert_eq!(result, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn rotate() {
+        let filled = || {
+            // Wrapped around, so rotating has to wrap too.
+            let mut rb = RingBuffer::<usize>::new(NonZeroUsize::new(5).unwrap());
+            for i in 0..7 {
+                rb.push_back(i);
+            }
+            rb
+        };
+
+        for n in [0, 2, 5, 6] {
+            let expected: Vec<usize> = (2..7).skip(n).collect();
+            let mut rb = filled();
+            rb.rotate_left(n);
+            assert_eq!(rb.to_vec(), expected);
+            assert_eq!(rb.len(), expected.len());
+
+            let expected: Vec<usize> = (2..7).take(5 - n.min(5)).collect();
+            let mut rb = filled();
+            rb.rotate_right(n);
+            assert_eq!(rb.to_vec(), expected);
+            assert_eq!(rb.len(), expected.len());
+        }
+
+        // Both ends keep working afterwards.
+        let mut rb = filled();
+        rb.rotate_left(1);
+        rb.rotate_right(1);
+        rb.push_back(7);
+        rb.push_front(1);
+        assert_eq!(rb.to_vec(), vec![1, 3, 4, 5, 7]);
+        assert_eq!(rb.pop_front(), Some(1));
+        assert_eq!(rb.pop_back(), Some(7));
+
+        let mut empty = RingBuffer::<usize>::new(NonZeroUsize::new(3).unwrap());
+        empty.rotate_left(1);
+        empty.rotate_right(1);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn position_of() {
+        let mut rb = RingBuffer::<usize>::new(NonZeroUsize::new(3).unwrap());
+        for i in 0..5 {
+            rb.push_back(i);
+        }
+        assert_eq!(rb.position_of(&2), Some(0));
+        assert_eq!(rb.position_of(&4), Some(2));
+        assert_eq!(rb.position_of(&0), None);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut rb = RingBuffer::<String>::new(NonZeroUsize::new(4).unwrap());
+        for i in 0..6 {
+            rb.push_back(i.to_string());
+        }
+        rb.pop_front();
+        rb.pop_front();
+
+        rb.shrink_to_fit();
+        assert_eq!(rb.get_values(), vec!["4", "5"]);
+        assert_eq!(rb.allocated(), 2);
+        assert_eq!(rb.capacity(), 4);
+
+        rb.push_back(String::from("6"));
+        rb.push_front(String::from("3"));
+        assert_eq!(rb.get_values(), vec!["3", "4", "5", "6"]);
+        assert_eq!(rb.allocated(), 4);
+        assert!(rb.is_full());
+        // Full at capacity: overwrites again instead of growing.
+        rb.push_back(String::from("7"));
+        assert_eq!(rb.get_values(), vec!["4", "5", "6", "7"]);
+        assert_eq!(rb.allocated(), 4);
+
+        while rb.pop_front().is_some() {}
+        rb.shrink_to_fit();
+        assert_eq!(rb.allocated(), 1);
+        rb.push_back(String::from("8"));
+        assert_eq!(rb.get_values(), vec!["8"]);
+    }
+
+    #[test]
+    fn unallocated() {
+        let mut rb = RingBuffer::<usize>::unallocated(NonZeroUsize::new(3).unwrap());
+        assert_eq!(rb.allocated(), 0);
+        rb.make_contiguous();
+        assert_eq!(rb.drain(..).count(), 0);
+        assert_eq!(rb.pop_front(), None);
+
+        for i in 0..5 {
+            rb.push_back(i);
+        }
+        assert_eq!(rb.allocated(), 3);
+        assert_eq!(rb.to_vec(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn grows_after_shrink() {
+        // Pushes and pops interleaved at every wrap-around position must
+        // keep FIFO order while the storage grows back.
+        for pops in 0..4 {
+            let mut rb = RingBuffer::<usize>::new(NonZeroUsize::new(8).unwrap());
+            let mut model = std::collections::VecDeque::new();
+            for i in 0..4 {
+                rb.push_back(i);
+                model.push_back(i);
+            }
+            rb.shrink_to_fit();
+            for _ in 0..pops {
+                assert_eq!(rb.pop_front(), model.pop_front());
+            }
+            for i in 4..8 {
+                rb.push_back(i);
+                model.push_back(i);
+                assert_eq!(rb.to_vec(), Vec::from(model.clone()));
+            }
+            for i in 8..10 {
+                rb.push_front(i);
+                model.push_front(i);
+                if model.len() > 8 {
+                    model.pop_back();
+                }
+                assert_eq!(rb.to_vec(), Vec::from(model.clone()));
+            }
+            assert!(rb.allocated() <= rb.capacity());
+        }
+    }
+
+    #[test]
+    fn make_contiguous() {
+        let mut rb = RingBuffer::<usize>::new(NonZeroUsize::new(4).unwrap());
+        for i in 0..6 {
+            rb.push_back(i);
+        }
+        rb.pop_front();
+        let expected = rb.get_values();
+
+        rb.make_contiguous();
+        assert_eq!(rb.head, 0);
+        assert_eq!(rb.tail, 3);
+        assert_eq!(rb.get_values(), expected);
+
+        rb.push_back(6);
+        assert_eq!(rb.get_values(), vec![3, 4, 5, 6]);
+        rb.make_contiguous();
+        assert_eq!((rb.head, rb.tail), (0, 0));
+        assert_eq!(rb.get_values(), vec![3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn remove() {
+        let mut rb = RingBuffer::<usize>::new(NonZeroUsize::new(4).unwrap());
+        for i in 0..6 {
+            rb.push_back(i);
+        }
+        assert_eq!(rb.remove(4), None);
+        assert_eq!(rb.remove(1), Some(3));
+        assert_eq!(rb.get_values(), vec![2, 4, 5]);
+        assert_eq!(rb.remove(2), Some(5));
+        assert_eq!(rb.remove(0), Some(2));
+        assert_eq!(rb.get_values(), vec![4]);
+
+        rb.push_back(6);
+        assert_eq!(rb.get_values(), vec![4, 6]);
+        assert_eq!(rb.len(), 2);
+    }
+
+    #[test]
+    fn extend_from_slice() {
+        let mut rb = RingBuffer::<usize>::new(NonZeroUsize::new(4).unwrap());
+        for i in 0..5 {
+            rb.push_back(i);
+        }
+        rb.pop_front();
+        rb.pop_front();
+        rb.extend_from_slice(&[5]);
+        assert_eq!(rb.get_values(), vec![3, 4, 5]);
+        assert_eq!(rb.capacity(), 4);
+
+        rb.extend_from_slice(&[6, 7]);
+        assert_eq!(rb.get_values(), vec![3, 4, 5, 6, 7]);
+        assert_eq!(rb.capacity(), 5);
+        assert!(rb.is_full());
+        rb.extend_from_slice(&[]);
+        assert_eq!(rb.len(), 5);
+    }
+
+    #[test]
+    fn drain() {
+        let mut rb = RingBuffer::<usize>::new(NonZeroUsize::new(5).unwrap());
+        for i in 0..7 {
+            rb.push_back(i);
+        }
+        assert_eq!(rb.drain(1..3).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(rb.get_values(), vec![2, 5, 6]);
+
+        rb.push_back(7);
+        assert_eq!(rb.get_values(), vec![2, 5, 6, 7]);
+        assert_eq!(rb.drain(..=0).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(rb.drain(2..).collect::<Vec<_>>(), vec![7]);
+        assert_eq!(rb.get_values(), vec![5, 6]);
+        assert_eq!(rb.drain(..).collect::<Vec<_>>(), vec![5, 6]);
+        assert!(rb.is_empty());
+        rb.push_back(8);
+        assert_eq!(rb.get_values(), vec![8]);
+    }
+
+    #[test]
+    fn peek_at() {
+        let mut rb = RingBuffer::<usize>::new(NonZeroUsize::new(3).unwrap());
+        assert_eq!(rb.peek_at(0), None);
+        for i in 0..5 {
+            rb.push_back(i);
+        }
+        assert_eq!(rb.peek_at(0), Some(&2));
+        assert_eq!(rb.peek_at(2), Some(&4));
+        assert_eq!(rb.peek_at(3), None);
+    }
+
+    #[test]
+    fn from_slice_and_to_vec() {
+        assert_eq!(RingBuffer::<usize>::from_slice(&[]).err(), Some(ConfigError::ZeroCapacity));
+        let mut rb = RingBuffer::from_slice(&[1, 2, 3]).unwrap();
+        assert!(rb.is_full());
+        assert_eq!(rb.to_vec(), vec![1, 2, 3]);
+        assert_eq!(rb.pop_front(), Some(1));
+        rb.push_back(4);
+        rb.push_back(5);
+        assert_eq!(rb.to_vec(), vec![3, 4, 5]);
+        assert_eq!(rb.pop_front(), Some(3));
+        assert_eq!(Vec::from(rb), vec![4, 5]);
+    }
+
+    #[test]
+    fn from_vec() {
+        assert!(RingBuffer::<String>::try_from(Vec::new()).is_err());
+        let mut rb = RingBuffer::try_from(vec!["a".to_string(), "b".to_string()]).unwrap();
+        assert_eq!(rb.capacity(), 2);
+        assert_eq!(rb.pop_front().as_deref(), Some("a"));
+        assert_eq!(rb.pop_front().as_deref(), Some("b"));
+        assert_eq!(rb.pop_front(), None);
+    }
+
+    #[test]
+    fn fixed_matches_heap() {
+        let mut fixed = FixedRingBuffer::<usize, 3>::new();
+        let mut heap = RingBuffer::<usize>::new(NonZeroUsize::new(3).unwrap());
+        assert_eq!(fixed.capacity(), heap.capacity());
+
+        for i in 0..5 {
+            let displaced = fixed.push_back(i);
+            assert_eq!(displaced, if i >= 3 { Some(i - 3) } else { None });
+            heap.push_back(i);
+            assert_eq!(fixed.iter().copied().collect::<Vec<_>>(), heap.get_values());
+        }
+        assert!(fixed.is_full());
+        assert_eq!(fixed.peek_front(), Some(&2));
+
+        for value in fixed.iter_mut() {
+            *value *= 10;
+        }
+        assert_eq!(fixed.pop_front(), Some(20));
+        assert_eq!(fixed.iter().copied().collect::<Vec<_>>(), vec![30, 40]);
+        assert_eq!(fixed.len(), 2);
+    }
+
+    #[test]
+    fn fixed_drops_its_elements() {
+        use std::rc::Rc;
+
+        let tracked = Rc::new(());
+        let mut fixed = FixedRingBuffer::<Rc<()>, 2>::new();
+        for _ in 0..3 {
+            fixed.push_back(Rc::clone(&tracked));
+        }
+        assert_eq!(Rc::strong_count(&tracked), 3);
+        drop(fixed);
+        assert_eq!(Rc::strong_count(&tracked), 1);
+    }
+}
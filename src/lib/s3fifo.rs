@@ -1,6 +1,135 @@
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt::{Debug, Write};
+use std::fs::File;
 use std::hash::Hash;
-use super::fifo_cache::FIFOCache;
+use std::io::{self, BufReader, BufWriter, Read};
+use std::mem::size_of;
+use std::num::NonZeroUsize;
+use std::ops::Index;
+use std::path::Path;
+use std::sync::Arc;
+use super::codec::{invalid_data, BinaryCodec};
+use super::entry::{Entry, OccupiedEntry, VacantEntry};
+use super::eviction_log::EvictionLog;
+use super::error::{CacheMiss, ConfigError, ConversionError};
+use super::fifo_cache::{CacheMetadata, CacheObject, FIFOCache, MAX_FREQ};
+#[cfg(feature = "admission_sketch")]
+use super::frequency_sketch::FrequencySketch;
+use super::policy::{CachePolicy, DefaultS3FIFOPolicy};
+use super::stats::CacheStats;
 
+///
+/// Length and capacity of each queue, as returned by `S3FIFO::segment_info`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentInfo {
+    pub small_len: usize,
+    pub small_capacity: usize,
+    pub main_len: usize,
+    pub main_capacity: usize,
+    pub ghost_len: usize,
+    pub ghost_capacity: usize,
+}
+
+///
+/// Difference between the cached contents of two caches, as returned by
+/// `S3FIFO::diff`. Frequencies, queue placement and ghost keys are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheDiff<K, V> {
+    pub only_in_self: Vec<K>,
+    pub only_in_other: Vec<K>,
+    /// `(key, value in self, value in other)`
+    pub changed: Vec<(K, V, V)>,
+}
+
+impl<K, V> CacheDiff<K, V> {
+    /// Whether both caches hold the same key-value pairs.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.changed.is_empty()
+    }
+}
+
+///
+/// Outcome of `S3FIFO::get_or_ghost`: which queue answered the lookup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheQueryResult<T> {
+    SmallHit(T),
+    MainHit(T),
+    /// Not cached, but recently evicted: inserting it again goes straight
+    /// to the main queue.
+    GhostHit,
+    Miss,
+}
+
+impl<T> CacheQueryResult<T> {
+    #[inline(always)]
+    pub fn is_hit(&self) -> bool {
+        matches!(self, CacheQueryResult::SmallHit(_) | CacheQueryResult::MainHit(_))
+    }
+
+    #[inline(always)]
+    pub fn value(&self) -> Option<&T> {
+        match self {
+            CacheQueryResult::SmallHit(value) | CacheQueryResult::MainHit(value) => Some(value),
+            CacheQueryResult::GhostHit | CacheQueryResult::Miss => None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn into_value(self) -> Option<T> {
+        match self {
+            CacheQueryResult::SmallHit(value) | CacheQueryResult::MainHit(value) => Some(value),
+            CacheQueryResult::GhostHit | CacheQueryResult::Miss => None,
+        }
+    }
+}
+
+///
+/// One operation of a batch passed to `S3FIFO::apply_batch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheOp<K, V> {
+    Get(K),
+    Put(K, V),
+    Remove(K),
+    /// An access whose value isn't needed.
+    Touch(K),
+}
+
+///
+/// Result of one `CacheOp`, in the same position as the op in its batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchResult<V> {
+    Hit(V),
+    Miss,
+    /// The value a `Put` replaced, if the key was already cached.
+    Inserted(Option<V>),
+    Removed(V),
+    Touched(bool),
+}
+
+///
+/// How a cache picks entries to evict, see `S3FIFO::with_strategy`.
+/// The single-queue strategies exist to compare against S3-FIFO on the
+/// same workload.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionStrategy {
+    /// Small, main and ghost queues with lazy promotion.
+    #[default]
+    S3FIFO,
+    /// A single FIFO queue: reads don't affect eviction order.
+    PureFIFO,
+    /// A single queue where every read moves the entry to the back.
+    /// Reads are O(n) in the cache size.
+    LRU,
+    /// A single queue evicting the most recently inserted entry first,
+    /// for large sequential scans.
+    MRU,
+}
+
+#[derive(Clone)]
 pub struct S3FIFO<K, V> {
     cache_size: usize,
     small_cache_capacity_ratio: f64,
@@ -10,9 +139,42 @@ pub struct S3FIFO<K, V> {
 
     s_queue: FIFOCache<K, V>,
     m_queue: FIFOCache<K, V>,
-    g_queue: FIFOCache<K, V>,
+    // Ghost entries only record that a key was recently evicted.
+    g_queue: FIFOCache<K, ()>,
+    ghost_enabled: bool,
+    /// Small queue entries with a frequency above this are promoted to
+    /// the main queue on eviction.
+    freq_promote_threshold: usize,
+    /// Promotion and main queue eviction decisions.
+    policy: Arc<dyn CachePolicy>,
+    strategy: EvictionStrategy,
+    /// Previous sample passed to `adaptive_ratio_update`.
+    last_hit_rate_sample: Option<f64>,
+    /// Opt-in record of evicted keys, see `with_eviction_log`.
+    eviction_log: Option<EvictionLog<K>>,
+    /// Access counts used to decide whether a ghost hit may displace the
+    /// main queue's next victim.
+    #[cfg(feature = "admission_sketch")]
+    sketch: FrequencySketch<K>,
+
+    size: usize,
+    stats: CacheStats,
+}
 
-    size: usize
+impl<K, V> S3FIFO<K, V> {
+    /// Capacity used by `S3FIFO::default()`.
+    pub const DEFAULT_CAPACITY: usize = 1024;
+    /// Small queue ratio used by `S3FIFO::default()` and
+    /// `S3FIFO::new_with_default_ratio`.
+    pub const DEFAULT_SMALL_RATIO: f64 = 0.1;
+    /// Promote entries read at least twice while in the small queue.
+    pub const DEFAULT_FREQ_PROMOTE_THRESHOLD: usize = 1;
+
+    /// Whether a small queue entry with frequency `freq` is promoted.
+    #[inline(always)]
+    fn promotes(&self, freq: usize) -> bool {
+        self.policy.should_promote(freq, self.freq_promote_threshold)
+    }
 }
 
 impl<K, V> S3FIFO<K,V> 
@@ -20,235 +182,4912 @@ where
     K: Default + Clone + Eq + Hash,
     V: Default + Clone,
 {
-    pub fn new(cache_size: usize, small_cache_ratio: f64) -> S3FIFO<K, V> {
-        assert!(small_cache_ratio > 0.0 && small_cache_ratio < 1.0);
+    ///
+    /// Panics if `small_cache_ratio` is not in `(0, 1)` or leaves a queue
+    /// without capacity; see `with_capacity_check`.
+    pub fn new(cache_size: NonZeroUsize, small_cache_ratio: f64) -> S3FIFO<K, V> {
+        Self::with_size(cache_size.get(), small_cache_ratio)
+    }
+
+    /// `new` for call sites with a plain `usize`. `None` on any invalid
+    /// configuration, including a zero `cache_size`.
+    pub fn try_new(cache_size: usize, small_cache_ratio: f64) -> Option<S3FIFO<K, V>> {
+        Self::with_capacity_check(cache_size, small_cache_ratio).ok()
+    }
+
+    /// `new` taking a `usize`, for the constructors built on top of it.
+    fn with_size(cache_size: usize, small_cache_ratio: f64) -> S3FIFO<K, V> {
+        Self::with_capacity_check(cache_size, small_cache_ratio)
+            .expect("invalid S3FIFO configuration")
+    }
+
+    ///
+    /// Like `new`, but reports an invalid configuration instead of
+    /// panicking.
+    pub fn with_capacity_check(
+        cache_size: usize, 
+        small_cache_ratio: f64
+    ) -> Result<S3FIFO<K, V>, ConfigError> {
+        if !(small_cache_ratio > 0.0 && small_cache_ratio < 1.0) {
+            return Err(ConfigError::InvalidRatio);
+        }
+        if cache_size == 0 {
+            return Err(ConfigError::ZeroCapacity);
+        }
 
         let small_cache_capacity = ((cache_size as f64) * small_cache_ratio) as usize;
         let main_cache_capacity = cache_size - small_cache_capacity;
 
-        assert!(main_cache_capacity > 0);
+        if small_cache_capacity == 0 {
+            return Err(ConfigError::SmallQueueZeroCapacity);
+        }
+        if main_cache_capacity == 0 {
+            return Err(ConfigError::MainQueueZeroCapacity);
+        }
 
         // Use the same capacity for ghost and main cache for now.
         let ghost_cache_capacity = main_cache_capacity;
 
-        S3FIFO { 
-            cache_size,
-            small_cache_capacity_ratio: small_cache_ratio,
+        let mut cache = Self::new_with_explicit_sizes(
             small_cache_capacity, 
             main_cache_capacity, 
-            ghost_cache_capacity,
-            s_queue: FIFOCache::new(small_cache_capacity), 
-            m_queue: FIFOCache::new(main_cache_capacity), 
-            g_queue: FIFOCache::new(ghost_cache_capacity),
-            size: 0
+            ghost_cache_capacity
+        );
+        cache.small_cache_capacity_ratio = small_cache_ratio;
+        Ok(cache)
+    }
+
+    ///
+    /// Sets each queue's capacity directly instead of deriving them from a
+    /// ratio. A `ghost_cap` of 0 disables the ghost queue, as in
+    /// `with_ghost_disabled`.
+    pub fn new_with_explicit_sizes(
+        small_cap: usize, 
+        main_cap: usize, 
+        ghost_cap: usize
+    ) -> S3FIFO<K, V> {
+        assert!(small_cap >= 1);
+        assert!(main_cap >= 1);
+        Self::with_small_queue(FIFOCache::new(small_cap), small_cap, main_cap, ghost_cap)
+    }
+
+    /// `new_with_explicit_sizes` around a given small queue of `small_cap`.
+    fn with_small_queue(
+        s_queue: FIFOCache<K, V>,
+        small_cap: usize, 
+        main_cap: usize, 
+        ghost_cap: usize
+    ) -> S3FIFO<K, V> {
+        let cache_size = small_cap + main_cap;
+        let ghost_enabled = ghost_cap > 0;
+
+        S3FIFO { 
+            cache_size,
+            small_cache_capacity_ratio: small_cap as f64 / cache_size as f64,
+            small_cache_capacity: small_cap, 
+            main_cache_capacity: main_cap, 
+            ghost_cache_capacity: ghost_cap,
+            s_queue, 
+            m_queue: FIFOCache::new(main_cap), 
+            // `RingBuffer` can't be zero-sized; with the ghost queue
+            // disabled it holds a single slot that is never allocated.
+            g_queue: if ghost_enabled { 
+                FIFOCache::new(ghost_cap) 
+            } else { 
+                FIFOCache::unallocated(1) 
+            },
+            ghost_enabled,
+            freq_promote_threshold: Self::DEFAULT_FREQ_PROMOTE_THRESHOLD,
+            policy: Arc::new(DefaultS3FIFOPolicy),
+            strategy: EvictionStrategy::S3FIFO,
+            last_hit_rate_sample: None,
+            eviction_log: None,
+            #[cfg(feature = "admission_sketch")]
+            sketch: FrequencySketch::new(cache_size),
+            size: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn new_with_default_ratio(cache_size: usize) -> S3FIFO<K, V> {
+        Self::with_size(cache_size, Self::DEFAULT_SMALL_RATIO)
+    }
+
+    ///
+    /// Like `new`, but small queue entries are promoted to the main queue
+    /// only when their frequency is above `freq_promote_threshold` (1 for
+    /// `new`). With 0 any entry read once is promoted, behaving close to
+    /// LRU; with `MAX_FREQ` nothing is promoted.
+    pub fn new_with_config(
+        cache_size: usize, 
+        small_cache_ratio: f64, 
+        freq_promote_threshold: usize
+    ) -> S3FIFO<K, V> {
+        let mut cache = Self::with_size(cache_size, small_cache_ratio);
+        cache.freq_promote_threshold = freq_promote_threshold;
+        cache
+    }
+
+    ///
+    /// A cache that never records evicted keys, for scan-heavy workloads
+    /// where nothing is re-requested. Every new key enters the small queue.
+    pub fn with_ghost_disabled(cache_size: usize, small_cache_ratio: f64) -> S3FIFO<K, V> {
+        let mut cache = Self::with_size(cache_size, small_cache_ratio);
+        cache.ghost_enabled = false;
+        cache.ghost_cache_capacity = 0;
+        cache.g_queue = FIFOCache::unallocated(1);
+        cache
+    }
+
+    ///
+    /// Like `new`, but promotion and main queue eviction are decided by
+    /// `policy` instead of `DefaultS3FIFOPolicy`.
+    pub fn with_policy(
+        cache_size: usize, 
+        small_cache_ratio: f64, 
+        policy: impl CachePolicy + 'static
+    ) -> S3FIFO<K, V> {
+        let mut cache = Self::with_size(cache_size, small_cache_ratio);
+        cache.policy = Arc::new(policy);
+        cache
+    }
+
+    ///
+    /// Record the last `max_size` keys fully evicted from the cache, for
+    /// debugging. Keys moved to the ghost queue are not recorded. Read
+    /// them back with `eviction_log`.
+    pub fn with_eviction_log(mut self, max_size: usize) -> S3FIFO<K, V> {
+        self.eviction_log = Some(EvictionLog::new(max_size));
+        self
+    }
+
+    ///
+    /// A cache evicting with `strategy`. The single-queue strategies keep every
+    /// entry in the main queue, which then gets the whole capacity; the
+    /// small and ghost queues go unused and `small_cache_ratio` is ignored.
+    pub fn with_strategy(
+        cache_size: usize, 
+        small_cache_ratio: f64, 
+        strategy: EvictionStrategy
+    ) -> S3FIFO<K, V> {
+        if strategy == EvictionStrategy::S3FIFO {
+            return Self::with_size(cache_size, small_cache_ratio);
         }
+
+        // The small queue keeps a capacity of 1, as `RingBuffer` can't be
+        // zero-sized, but never allocates it.
+        let mut cache = Self::with_small_queue(FIFOCache::unallocated(1), 1, cache_size, 0);
+        cache.cache_size = cache_size;
+        cache.small_cache_capacity = 0;
+        cache.small_cache_capacity_ratio = 0.0;
+        cache.strategy = strategy;
+        cache
     }
 
-    pub fn new_with_default_ratio(cache_size: usize) {
-        Self::new(cache_size, 0.1);
+    ///
+    /// Rebuild a cache from the output of `snapshot`. Entries are placed
+    /// by frequency as in `warm_from_iter`, so which queue an entry lands
+    /// in may differ from the cache the snapshot was taken from. Unlike
+    /// `warm_from_iter`, cold entries overflow into the main queue once the
+    /// small queue is full, so a snapshot that fits is restored whole.
+    pub fn restore_from_snapshot(
+        capacity: usize, 
+        small_ratio: f64, 
+        snapshot: Vec<(K, V, usize)>
+    ) -> S3FIFO<K, V> {
+        let mut cache = Self::with_size(capacity, small_ratio);
+        cache.restore_entries(snapshot);
+        cache
     }
 
 }
 
 ///
-/// User-facing/client-facing APIs.
-impl<K, V> S3FIFO<K,V> 
+/// Resizing. Queues are rebuilt, so these need the same bounds as the
+/// constructors.
+impl<K, V> S3FIFO<K, V>
 where 
-    K: Eq + Hash,
+    K: Default + Clone + Eq + Hash,
+    V: Default + Clone,
 {
-    pub fn get(&mut self, key: &K) -> Option<&V> 
-    where K: Clone 
-    {
-        if let Some(obj) = self.s_queue.find(key) {
-            return Some(&obj);
-        }
+    ///
+    /// Change the total capacity, splitting it with the current small
+    /// queue ratio (each queue keeps at least one slot). Entries are kept
+    /// in queue order; if a queue shrinks, its oldest entries go through
+    /// the usual eviction policy.
+    pub fn resize(&mut self, new_size: usize) {
+        let (small, main) = if self.strategy == EvictionStrategy::S3FIFO {
+            assert!(new_size >= 2);
+            let small = (((new_size as f64) * self.small_cache_capacity_ratio) as usize)
+                .clamp(1, new_size - 1);
+            (small, new_size - small)
+        } else {
+            assert!(new_size >= 1);
+            (0, new_size)
+        };
 
-        if let Some(obj) = self.m_queue.find(key) {
-            return Some(&obj);
+        self.cache_size = new_size;
+        self.small_cache_capacity = small;
+        self.main_cache_capacity = main;
+        let new_small = if small == 0 { FIFOCache::unallocated(1) } else { FIFOCache::new(small) };
+        let old_small = std::mem::replace(&mut self.s_queue, new_small);
+        let old_main = std::mem::replace(&mut self.m_queue, FIFOCache::new(main));
+        self.size = 0;
+
+        if self.ghost_enabled {
+            self.ghost_cache_capacity = main;
+            let old_ghost = std::mem::replace(&mut self.g_queue, FIFOCache::new(main));
+            let keys: Vec<K> = old_ghost.keys().cloned().collect();
+            for key in keys.into_iter().rev().take(main).rev() {
+                self.g_queue.insert(key, ());
+            }
         }
 
-        return None
+        // Main first, so promotions out of an overfull small queue land
+        // behind the entries that were already there.
+        for (key, value, meta) in Self::drain_with_meta(old_main) {
+            self.push_main(key, value, meta);
+        }
+        for (key, value, meta) in Self::drain_with_meta(old_small) {
+            self.push_small(key, value, meta);
+        }
     }
 
-    pub fn get_copy(&mut self, key: &K) -> Option<V>
-    where K: Clone, V: Clone 
-    {
-        if let Some(value) = self.get(key) {
-            Some(value.clone())
-        } else {
-            None
-        }
+    ///
+    /// Change the small queue ratio, keeping the total capacity. Works
+    /// like `resize`: if the small queue shrinks, its oldest entries are
+    /// promoted or evicted as usual; if the main queue shrinks, it evicts.
+    /// Entries that still fit are kept. Panics unless `new_small_ratio` is
+    /// in `(0, 1)`. Single-queue strategies only record the ratio.
+    pub fn resize_ratio(&mut self, new_small_ratio: f64) {
+        assert!(
+            new_small_ratio > 0.0 && new_small_ratio < 1.0,
+            "small queue ratio must be in (0, 1), got {}", new_small_ratio
+        );
+        self.small_cache_capacity_ratio = new_small_ratio;
+        self.resize(self.cache_size);
     }
 
-    // TODO: TTL supports
-    pub fn put(&mut self, key: K, value: V)
-    where K: Clone, V: Clone
-    {
-        if let Some(obj) = self.s_queue.find_mut(&key) {
-            obj.set_value(value);
+    ///
+    /// Nudge the small queue ratio by 0.01 (within `[0.05, 0.5]`) based on
+    /// a hit rate sample, then `resize` to apply it. This is a heuristic:
+    /// sample `CacheStats::hit_rate` every N operations and pass it in.
+    /// 
+    /// If the sample is lower than the previous one, the small queue grows
+    /// to capture more new entries. Otherwise, if most of the small queue
+    /// is waiting to be promoted, it shrinks. The first call only records
+    /// the sample. Single-queue strategies are left alone.
+    pub fn adaptive_ratio_update(&mut self, observed_hit_rate: f64) {
+        const STEP: f64 = 0.01;
+        const MIN_RATIO: f64 = 0.05;
+        const MAX_RATIO: f64 = 0.5;
+
+        let previous = self.last_hit_rate_sample.replace(observed_hit_rate);
+        if self.strategy != EvictionStrategy::S3FIFO {
             return;
-        } 
-        
-        if let Some(obj) = self.m_queue.find_mut(&key) {
-            obj.set_value(value);
+        }
+        let Some(previous) = previous else {
+            return;
+        };
+
+        let ratio = self.small_cache_capacity_ratio;
+        let to_promote = self.s_queue.iter_in_order()
+            .filter(|(_, obj)| self.promotes(obj.get_freq()))
+            .count();
+        let new_ratio = if observed_hit_rate < previous {
+            ratio + STEP
+        } else if to_promote * 2 > self.s_queue.len() {
+            ratio - STEP
+        } else {
             return;
+        };
+
+        self.small_cache_capacity_ratio = new_ratio.clamp(MIN_RATIO, MAX_RATIO);
+        self.resize(self.cache_size);
+    }
+
+    ///
+    /// Combine two caches, e.g. to consolidate shards. The result has the
+    /// summed capacity, the average small queue ratio and otherwise the
+    /// default configuration; stats start from zero. Entries keep their
+    /// queue and frequency, `a`'s ahead of `b`'s. A key cached in both
+    /// keeps the entry with the higher frequency, `a`'s on a tie. Ghost
+    /// keys of both are kept as far as the ghost queue fits them.
+    pub fn merge(a: S3FIFO<K, V>, b: S3FIFO<K, V>) -> S3FIFO<K, V> {
+        let ratio = (a.small_cache_capacity_ratio + b.small_cache_capacity_ratio) / 2.0;
+        let mut merged = Self::with_size(a.cache_size + b.cache_size, ratio);
+
+        let a_keys: HashSet<K> = a.s_queue.keys().chain(a.m_queue.keys()).cloned().collect();
+        let b_wins: HashSet<K> = b.s_queue.iter_in_order()
+            .chain(b.m_queue.iter_in_order())
+            .filter(|(key, obj)| {
+                a.s_queue.peek(key)
+                    .or_else(|| a.m_queue.peek(key))
+                    .is_some_and(|ours| obj.get_freq() > ours.get_freq())
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        let ghosts: Vec<K> = a.g_queue.keys().chain(b.g_queue.keys()).cloned().collect();
+
+        let keep_a = |key: &K| !b_wins.contains(key);
+        let keep_b = |key: &K| !a_keys.contains(key) || b_wins.contains(key);
+        let (a_main, a_small) = (Self::drain_with_meta(a.m_queue), Self::drain_with_meta(a.s_queue));
+        let (b_main, b_small) = (Self::drain_with_meta(b.m_queue), Self::drain_with_meta(b.s_queue));
+
+        let main = a_main.into_iter().filter(|(key, ..)| keep_a(key))
+            .chain(b_main.into_iter().filter(|(key, ..)| keep_b(key)));
+        for (key, value, meta) in main {
+            merged.push_main(key, value, meta);
+        }
+        let small = a_small.into_iter().filter(|(key, ..)| keep_a(key))
+            .chain(b_small.into_iter().filter(|(key, ..)| keep_b(key)));
+        for (key, value, meta) in small {
+            merged.push_small(key, value, meta);
         }
 
-        // NOT FOUND in cache
-        self.insert(key, value);
+        for key in ghosts {
+            if merged.peek(&key).is_some() || merged.g_queue.peek(&key).is_some() {
+                continue;
+            }
+            if merged.g_queue.is_full() { merged.g_queue.evict(); }
+            merged.g_queue.insert(key, ());
+        }
+        merged
     }
 
-    #[inline(always)]
-    pub fn is_full(&self) -> bool {
-        self.size == self.cache_size
+    fn drain_with_meta(mut queue: FIFOCache<K, V>) -> Vec<(K, V, CacheMetadata)> {
+        std::iter::from_fn(|| queue.evict())
+            .map(|(key, obj)| {
+                let meta = obj.get_meta_copy();
+                (key, obj.into_value(), meta)
+            })
+            .collect()
     }
 }
 
-/// 
-/// Some internal functions
-/// They are develoepr-facing APIs.
-impl<K, V> S3FIFO<K, V> 
+///
+/// A cache with `DEFAULT_CAPACITY` entries and the default small queue
+/// ratio. Use `S3FIFO::new` when the cache needs a specific size.
+impl<K, V> Default for S3FIFO<K, V>
 where 
-    K: Clone + Eq + Hash, 
-    V: Clone
+    K: Default + Clone + Eq + Hash,
+    V: Default + Clone,
 {
-    fn insert(&mut self, key: K, value: V) 
-    {
-        while self.is_full() { self.evict() }
+    fn default() -> Self {
+        Self::with_size(Self::DEFAULT_CAPACITY, Self::DEFAULT_SMALL_RATIO)
+    }
+}
 
-        // Found in ghost queue
-        if let Some(_) = self.g_queue.find(&key) {
-            self.m_queue.insert(key, value);
-        } else {
-            self.s_queue.insert(key, value);
+///
+/// A cache holding exactly the map's entries: its capacity is `map.len()`
+/// (at least 2, one slot per queue) with the default small queue ratio,
+/// rounded so the small queue has at least one slot.
+impl<K, V> TryFrom<HashMap<K, V>> for S3FIFO<K, V>
+where 
+    K: Default + Clone + Eq + Hash,
+    V: Default + Clone,
+{
+    type Error = ConversionError;
+
+    fn try_from(map: HashMap<K, V>) -> Result<Self, Self::Error> {
+        if map.is_empty() {
+            return Err(ConversionError::EmptyMap);
         }
 
-        self.size += 1;
+        let capacity = map.len().max(2);
+        let small = (((capacity as f64) * Self::DEFAULT_SMALL_RATIO) as usize).max(1);
+        let mut cache = Self::new_with_explicit_sizes(small, capacity - small, capacity - small);
+        cache.restore_entries(map.into_iter().map(|(key, value)| (key, value, 0)));
+        Ok(cache)
     }
+}
 
-    #[inline(always)]
-    fn evict(&mut self) {
-        if self.s_queue.is_full() {
-            self.evict_s();
+///
+/// Every cached entry of the small and main queues. Frequencies and the
+/// ghost queue are dropped.
+impl<K, V> From<S3FIFO<K, V>> for HashMap<K, V>
+where 
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    fn from(mut cache: S3FIFO<K, V>) -> Self {
+        let mut map = HashMap::with_capacity(cache.len());
+        while let Some((key, obj)) = cache.pop_front_entry() {
+            map.insert(key, obj.get_value_copy());
         }
+        map
+    }
+}
 
-        if self.m_queue.is_full() {
-            self.evict_m();
+const FILE_MAGIC: &[u8; 4] = b"S3FF";
+/// Bump on any incompatible change to the file layout.
+const FILE_VERSION: u32 = 2;
+
+const TAG_SMALL: u8 = 0;
+const TAG_MAIN: u8 = 1;
+const TAG_GHOST: u8 = 2;
+
+///
+/// Persistence.
+///
+/// File layout, integers little-endian: magic `S3FF`, version (u32),
+/// small, main and ghost capacities (u64 each), small queue ratio (f64
+/// bits), ghost queue enabled (u8), promotion threshold (u64), record
+/// count (u64), then one record per entry: queue tag (u8), frequency (u8),
+/// key, value. Ghost records have no value. Keys and values use their
+/// `BinaryCodec`.
+impl<K, V> S3FIFO<K, V>
+where 
+    K: BinaryCodec + Default + Clone + Eq + Hash,
+    V: BinaryCodec + Default + Clone,
+{
+    ///
+    /// Write every queue, in order and with frequencies, to `path`. TTLs
+    /// and statistics are not saved. Only the default `S3FIFO` strategy
+    /// is supported.
+    pub fn save_to_file(&self, path: &Path) -> io::Result<()> {
+        if self.strategy != EvictionStrategy::S3FIFO {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput, 
+                "only the S3FIFO strategy can be saved"
+            ));
         }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        io::Write::flush(&mut writer)
     }
 
-    #[inline(always)]
-    fn evict_s(&mut self) 
-    {
-        let mut evicted = false;
-        while !evicted && !self.s_queue.empty() {
-            if let Some((key, obj)) = self.s_queue.evict() {
-                if obj.get_freq() > 1 {
-                    self.m_queue.insert(key, obj.get_value_copy());
-                    if self.m_queue.is_full() { self.evict_m() }
-                } else {
-                    self.g_queue.insert(key, obj.get_value_copy());
-                    evicted = true;
-                }
+    /// Rebuild a cache written by `save_to_file`, with every entry back in
+    /// its queue at its position and frequency.
+    pub fn load_from_file(path: &Path) -> io::Result<S3FIFO<K, V>> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    fn write_to<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        // A ghost key can sit in the ring buffer twice; keep its newest
+        // slot, as `collect_garbage` does, so the file loads back.
+        let mut ghosts = Vec::new();
+        if self.ghost_enabled {
+            let mut seen = HashSet::new();
+            ghosts = self.g_queue.keys().collect();
+            ghosts.reverse();
+            ghosts.retain(|key| seen.insert(*key));
+            ghosts.reverse();
+        }
+
+        writer.write_all(FILE_MAGIC)?;
+        FILE_VERSION.encode(writer)?;
+        self.small_cache_capacity.encode(writer)?;
+        self.main_cache_capacity.encode(writer)?;
+        self.ghost_cache_capacity.encode(writer)?;
+        self.small_cache_capacity_ratio.to_bits().encode(writer)?;
+        self.ghost_enabled.encode(writer)?;
+        self.freq_promote_threshold.encode(writer)?;
+        (self.len() + ghosts.len()).encode(writer)?;
+
+        for (tag, queue) in [(TAG_SMALL, &self.s_queue), (TAG_MAIN, &self.m_queue)] {
+            for (key, obj) in queue.iter_in_order() {
+                tag.encode(writer)?;
+                (obj.get_freq() as u8).encode(writer)?;
+                key.encode(writer)?;
+                obj.get_value().encode(writer)?;
             }
         }
+        for key in ghosts {
+            TAG_GHOST.encode(writer)?;
+            0u8.encode(writer)?;
+            key.encode(writer)?;
+        }
+        Ok(())
     }
 
-    #[inline(always)]
-    fn evict_m(&mut self) 
-    {
-        let mut evicted = false;
-        while !evicted && !self.m_queue.empty() {
-            if let Some((key, obj)) = self.m_queue.evict() {
-                if obj.get_freq() > 0 {
-                    let mut meta = obj.get_meta_copy(); 
-                    meta.desc_freq();
-                    
-                    self.m_queue.insert_with_meta(
-                        key, 
-                        obj.get_value_copy(),
-                        meta 
-                    );
-                    // self.m_queue.insert(key, obj.get_value_copy());
-                } else {
-                    evicted = true;
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<S3FIFO<K, V>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != FILE_MAGIC {
+            return Err(invalid_data("not an S3FIFO cache file"));
+        }
+        let version = u32::decode(reader)?;
+        if version != FILE_VERSION {
+            return Err(invalid_data(format!("unsupported cache file version {}", version)));
+        }
+
+        let small_cap = usize::decode(reader)?;
+        let main_cap = usize::decode(reader)?;
+        let ghost_cap = usize::decode(reader)?;
+        let ratio = f64::from_bits(u64::decode(reader)?);
+        let ghost_enabled = bool::decode(reader)?;
+        let threshold = usize::decode(reader)?;
+        if small_cap == 0 || main_cap == 0 || (ghost_enabled && ghost_cap == 0) {
+            return Err(invalid_data("invalid queue capacities in cache file"));
+        }
+        if !(ratio > 0.0 && ratio < 1.0) {
+            return Err(invalid_data(ConfigError::InvalidRatio));
+        }
+
+        let mut cache = Self::new_with_explicit_sizes(small_cap, main_cap, ghost_cap);
+        cache.small_cache_capacity_ratio = ratio;
+        cache.ghost_enabled = ghost_enabled;
+        cache.freq_promote_threshold = threshold;
+
+        let records = usize::decode(reader)?;
+        for _ in 0..records {
+            let tag = u8::decode(reader)?;
+            let meta = CacheMetadata::with_freq(u8::decode(reader)? as usize);
+            let key = K::decode(reader)?;
+            if cache.peek(&key).is_some() || cache.g_queue.peek(&key).is_some() {
+                return Err(invalid_data("duplicate key in cache file"));
+            }
+
+            match tag {
+                TAG_SMALL => cache.push_small(key, V::decode(reader)?, meta),
+                TAG_MAIN => cache.push_main(key, V::decode(reader)?, meta),
+                TAG_GHOST => {
+                    if cache.ghost_enabled {
+                        cache.g_queue.insert(key, ());
+                    }
                 }
+                _ => return Err(invalid_data(format!("unknown queue tag {}", tag))),
             }
         }
+        Ok(cache)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::S3FIFO;
-
-    #[test]
-    fn init() {
-        let cache: S3FIFO<isize, isize> = S3FIFO::new(100, 0.1);
+///
+/// A cache of reference-counted values. Handing out a value is an
+/// `Arc::clone` instead of a deep copy, which pays off for large values.
+/// 
+/// ```
+/// use std::num::NonZeroUsize;
+/// use std::sync::Arc;
+/// use s3fifo::prelude::*;
+///
+/// let capacity = NonZeroUsize::new(100).unwrap();
+/// let mut cache: SharedS3FIFO<u32, Vec<u8>> = S3FIFO::new_shared(capacity, 0.1);
+/// cache.put(0, Arc::new(vec![0; 4096]));
+///
+/// let blob = cache.get_shared(&0).unwrap();
+/// assert_eq!(blob.len(), 4096);
+/// ```
+pub type SharedS3FIFO<K, V> = S3FIFO<K, Arc<V>>;
 
-        assert_eq!(cache.cache_size, 100);
-        assert_eq!(cache.small_cache_capacity_ratio, 0.1);
-        assert_eq!(cache.small_cache_capacity, 10);
-        assert_eq!(cache.size, 0);
+impl<K, V> S3FIFO<K, Arc<V>>
+where 
+    K: Default + Clone + Eq + Hash,
+    V: Default,
+{
+    pub fn new_shared(cache_size: NonZeroUsize, small_cache_ratio: f64) -> SharedS3FIFO<K, V> {
+        Self::new(cache_size, small_cache_ratio)
     }
+}
 
-    #[test]
-    fn empty_get() {
-        let mut cache: S3FIFO<isize, isize> = S3FIFO::new(100, 0.1);
-        let result = cache.get(&1);
-        assert!(result.is_none());
-        assert_eq!(cache.size, 0);
+impl<K, V> S3FIFO<K, Arc<V>>
+where 
+    K: Clone + Eq + Hash,
+{
+    /// Like `get_copy`, but only bumps the reference count.
+    #[inline(always)]
+    pub fn get_shared(&mut self, key: &K) -> Option<Arc<V>> {
+        self.get(key).map(Arc::clone)
     }
+}
 
-    #[test]
-    fn put_value() {
-        let mut cache: S3FIFO<isize, isize> = S3FIFO::new(100, 0.1);
-        cache.put(0, 0);
+///
+/// A cache of lists, e.g. all comments of a post, grown and shrunk one
+/// item at a time.
+impl<K, V> S3FIFO<K, Vec<V>>
+where 
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    ///
+    /// Append `item` to the list cached under `key`, or cache `vec![item]`
+    /// if there is none. Counts as an access, like `put`.
+    pub fn push_to_value(&mut self, key: K, item: V) {
+        if let Ok(items) = self.try_get_mut(&key) {
+            items.push(item);
+            return;
+        }
+        self.put(key, vec![item]);
+    }
 
-        assert_eq!(cache.size, 1);
-        assert_eq!(cache.s_queue.len(), 1);
-        assert_eq!(cache.m_queue.len(), 0);
-        assert_eq!(cache.g_queue.len(), 0);
+    ///
+    /// Pop the last item of the list cached under `key`. The entry is
+    /// removed once its list is empty, without a ghost entry, as with
+    /// `take`. Counts as an access.
+    pub fn pop_from_value(&mut self, key: &K) -> Option<V> {
+        let items = self.try_get_mut(key).ok()?;
+        let item = items.pop();
+        if items.is_empty() {
+            self.take(key);
+        }
+        item
+    }
+}
 
-        cache.put(1, 1);
-        assert_eq!(cache.size, 2);
-        assert_eq!(cache.s_queue.len(), 2);
-        assert_eq!(cache.m_queue.len(), 0);
-        assert_eq!(cache.g_queue.len(), 0);
+///
+/// User-facing/client-facing APIs.
+impl<K, V> S3FIFO<K,V> 
+where 
+    K: Eq + Hash,
+{
+    #[inline(always)]
+    pub fn get(&mut self, key: &K) -> Option<&V> 
+    where K: Clone 
+    {
+        self.get_or_ghost(key).into_value()
     }
 
-    #[test]
+    ///
+    /// Like `get`, but also tells which queue answered, and whether a
+    /// missing key is still remembered by the ghost queue. A ghost hit
+    /// counts as a miss and leaves every live entry untouched.
+    pub fn get_or_ghost(&mut self, key: &K) -> CacheQueryResult<&V>
+    where K: Clone
+    {
+        #[cfg(feature = "admission_sketch")]
+        self.sketch.increment(key);
+
+        if let Some(obj) = self.s_queue.find(key) {
+            self.stats.hits += 1;
+            self.stats.small_queue_hits += 1;
+            return CacheQueryResult::SmallHit(obj);
+        }
+
+        if self.strategy == EvictionStrategy::LRU {
+            self.m_queue.move_to_back(key);
+        }
+
+        if let Some(obj) = self.m_queue.find(key) {
+            self.stats.hits += 1;
+            self.stats.main_queue_hits += 1;
+            return CacheQueryResult::MainHit(obj);
+        }
+
+        self.stats.misses += 1;
+        if self.ghost_enabled && self.g_queue.peek(key).is_some() {
+            CacheQueryResult::GhostHit
+        } else {
+            CacheQueryResult::Miss
+        }
+    }
+
+    ///
+    /// The entry for `key`, for in-place insertion or update. Looking up
+    /// an occupied entry counts as an access, same as `get`.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V>
+    where K: Clone
+    {
+        if self.get(&key).is_some() {
+            Entry::Occupied(OccupiedEntry { cache: self, key })
+        } else {
+            Entry::Vacant(VacantEntry { cache: self, key })
+        }
+    }
+
+    /// The value of `key`, inserting `V::default()` on a miss. Shorthand
+    /// for `entry(key).or_default()`.
+    pub fn get_or_insert_default(&mut self, key: K) -> &V
+    where K: Clone, V: Clone + Default
+    {
+        self.entry(key).or_default()
+    }
+
+    /// Mutable counterpart of `get_or_insert_default`, e.g. for counters:
+    /// `*cache.get_mut_or_insert_default(key) += 1`.
+    pub fn get_mut_or_insert_default(&mut self, key: K) -> &mut V
+    where K: Clone, V: Clone + Default
+    {
+        self.entry(key).or_default()
+    }
+
+    pub fn get_copy(&mut self, key: &K) -> Option<V>
+    where K: Clone, V: Clone 
+    {
+        self.get(key).cloned()
+    }
+
+    ///
+    /// Like `get`, but for code where a miss is a bug: the error carries
+    /// the key. Takes `&self`, so this is not counted as an access.
+    pub fn get_or_err<'a>(&self, key: &'a K) -> Result<&V, CacheMiss<&'a K>> {
+        self.peek(key).ok_or(CacheMiss { key })
+    }
+
+    /// Mutable counterpart of `get_or_err`. This one counts as an access.
+    pub fn try_get_mut<'a>(&mut self, key: &'a K) -> Result<&mut V, CacheMiss<&'a K>>
+    where K: Clone
+    {
+        if self.get(key).is_none() {
+            return Err(CacheMiss { key });
+        }
+        Ok(self.peek_mut(key).expect("key was just found"))
+    }
+
+    ///
+    /// Mutable references to the values of several keys at once, in the
+    /// order of `keys`, `None` for a miss. Each key counts as an access.
+    /// O(len) as every entry is visited once. Panics if `keys` contains
+    /// the same key twice, since the references would alias.
+    pub fn get_multi_mut(&mut self, keys: &[K]) -> Vec<Option<&mut V>>
+    where K: Clone
+    {
+        let mut slots = HashMap::with_capacity(keys.len());
+        for (slot, key) in keys.iter().enumerate() {
+            assert!(slots.insert(key, slot).is_none(), "get_multi_mut called with duplicate keys");
+        }
+        for key in keys {
+            self.get(key);
+        }
+
+        let mut values: Vec<Option<&mut V>> = (0..keys.len()).map(|_| None).collect();
+        for (key, obj) in self.s_queue.entries_mut().chain(self.m_queue.entries_mut()) {
+            if let Some(&slot) = slots.get(key) {
+                values[slot] = Some(obj.get_value_mut());
+            }
+        }
+        values
+    }
+
+    ///
+    /// `get` the first entry whose key matches `predicate`, scanning the
+    /// small queue then the main queue, each front to back. Counts as an
+    /// access of that entry. O(n).
+    pub fn get_by_predicate<P>(&mut self, predicate: P) -> Option<&V>
+    where 
+        K: Clone,
+        P: Fn(&K) -> bool,
+    {
+        let key = self.get_key_by_predicate(predicate)?.clone();
+        self.get(&key)
+    }
+
+    /// The key `get_by_predicate` would pick, without touching
+    /// frequencies or stats.
+    pub fn get_key_by_predicate<P>(&self, predicate: P) -> Option<&K>
+    where P: Fn(&K) -> bool
+    {
+        self.s_queue.find_by_key_pred(&predicate)
+            .or_else(|| self.m_queue.find_by_key_pred(&predicate))
+    }
+
+    /// Like `get_copy`, but a miss yields `V::default()`. Nothing is inserted.
+    pub fn get_copy_or_default(&mut self, key: &K) -> V
+    where K: Clone, V: Clone + Default
+    {
+        self.get_copy(key).unwrap_or_default()
+    }
+
+    ///
+    /// Like `atomic_get_put`, but for a borrowed key and a value that
+    /// doesn't depend on it. The key is cloned only on a miss.
+    pub fn get_or_else(&mut self, key: &K, f: impl FnOnce() -> V) -> &V
+    where K: Clone, V: Clone
+    {
+        self.get_or_compute(key, |_| f())
+    }
+
+    ///
+    /// The cached value for `key`, or `fallback()` on a miss. Unlike
+    /// `get_or_else`, the computed value is not inserted, e.g. to serve
+    /// something while the backing service is degraded.
+    pub fn get_with_fallback<F>(&mut self, key: &K, fallback: F) -> Cow<'_, V>
+    where K: Clone, V: Clone, F: FnOnce() -> V
+    {
+        match self.get(key) {
+            Some(value) => Cow::Borrowed(value),
+            None => Cow::Owned(fallback()),
+        }
+    }
+
+    ///
+    /// Insert or update `key`. Returns the previous value if the key was
+    /// already cached.
+    // No TTL here: expiry is per queue (`FIFOCache::insert_with_ttl`), and
+    // an entry dropped by its queue on lookup would leave `size` stale.
+    pub fn put(&mut self, key: K, value: V) -> Option<V>
+    where K: Clone, V: Clone
+    {
+        if let Some(obj) = self.s_queue.find_mut(&key) {
+            return Some(obj.replace_value(value));
+        } 
+        
+        if let Some(obj) = self.m_queue.find_mut(&key) {
+            return Some(obj.replace_value(value));
+        }
+
+        // NOT FOUND in cache
+        self.insert(key, value);
+        None
+    }
+
+    ///
+    /// Load an entry ahead of demand. It enters the small queue with
+    /// frequency 0 regardless of the ghost queue, and if it is evicted
+    /// before anyone reads it, it is dropped without a ghost entry, so
+    /// prefetching can't crowd hot keys out. No-op if `key` is cached.
+    pub fn prefetch(&mut self, key: K, value: V)
+    where K: Clone, V: Clone
+    {
+        if self.peek(&key).is_some() {
+            return;
+        }
+
+        self.make_room();
+        self.push_small(key, value, CacheMetadata::prefetched());
+        self.stats.prefetch_count += 1;
+    }
+
+    ///
+    /// Insert entries whose popularity is known up front. `high` entries
+    /// go straight to the main queue, as if they were ghost hits; `low`
+    /// entries enter the small queue like `put`. Keys already cached only
+    /// have their value replaced.
+    pub fn insert_batch_with_priority(
+        &mut self, 
+        high: Vec<(K, V)>, 
+        low: Vec<(K, V)>
+    )
+    where K: Clone, V: Clone
+    {
+        for (key, value) in high {
+            if let Some(cached) = self.peek_mut(&key) {
+                *cached = value;
+                continue;
+            }
+            self.make_room();
+            self.push_main(key, value, CacheMetadata::default());
+        }
+        for (key, value) in low {
+            if let Some(cached) = self.peek_mut(&key) {
+                *cached = value;
+                continue;
+            }
+            self.make_room();
+            self.push_small(key, value, CacheMetadata::default());
+        }
+    }
+
+    ///
+    /// Like `put`, but the entry starts with (or is reset to) the given
+    /// access frequency, clamped to `MAX_FREQ`. Use it to pre-warm the cache
+    /// with entries known to be hot: with `freq >= 2` an entry is promoted
+    /// to the main queue as soon as it leaves the small queue.
+    pub fn put_with_freq(
+        &mut self, 
+        key: K, 
+        value: V, 
+        freq: usize
+    ) -> Option<V>
+    where K: Clone, V: Clone
+    {
+        if let Some(obj) = self.s_queue.find_mut(&key) {
+            obj.set_freq(freq);
+            return Some(obj.replace_value(value));
+        }
+
+        if let Some(obj) = self.m_queue.find_mut(&key) {
+            obj.set_freq(freq);
+            return Some(obj.replace_value(value));
+        }
+
+        self.insert_with_meta(key, value, CacheMetadata::with_freq(freq));
+        None
+    }
+
+    ///
+    /// Insert an entry at the tail of the main queue, the position furthest
+    /// from eviction, with the given frequency (clamped to `MAX_FREQ`). It
+    /// skips the small queue, for pre-warming keys known to be very hot. A
+    /// cached key is moved there and its previous value returned. Under
+    /// the `MRU` strategy the tail is evicted first.
+    pub fn insert_at_tail_of_main(
+        &mut self, 
+        key: K, 
+        value: V, 
+        freq: usize
+    ) -> Option<V>
+    where K: Clone, V: Clone
+    {
+        let previous = self.s_queue.take(&key).or_else(|| self.m_queue.take(&key));
+        if previous.is_some() {
+            self.size -= 1;
+        }
+
+        self.make_room();
+        self.push_main(key, value, CacheMetadata::with_freq(freq));
+        previous
+    }
+
+    ///
+    /// Pre-populate the cache from `(key, value, freq)` triples, e.g. a
+    /// persisted access log. Entries hot enough to be promoted (frequency
+    /// above `freq_promote_threshold`)
+    /// go straight to the main queue, the rest to the small queue. The
+    /// ghost queue is left alone. Keys that are already cached are updated
+    /// in place as with `put_with_freq`.
+    pub fn warm_from_iter(
+        &mut self, 
+        iter: impl IntoIterator<Item = (K, V, usize)>
+    )
+    where K: Clone, V: Clone
+    {
+        for (key, value, freq) in iter {
+            if self.peek(&key).is_some() {
+                self.put_with_freq(key, value, freq);
+                continue;
+            }
+
+            self.make_room();
+            let meta = CacheMetadata::with_freq(freq);
+            if self.promotes(freq) {
+                self.push_main(key, value, meta);
+            } else {
+                self.push_small(key, value, meta);
+            }
+        }
+    }
+
+    ///
+    /// Move every cached entry of `other` into `self` through `put`,
+    /// small queue first, evicting from `self` as needed. `other` ends up
+    /// empty; its ghost queue is left as is and not transferred.
+    /// 
+    /// Frequencies are not carried over: moved entries start at zero.
+    /// Use `transfer_with_freq` to keep them.
+    pub fn transfer(&mut self, other: &mut S3FIFO<K, V>)
+    where K: Clone, V: Clone
+    {
+        while let Some((key, obj)) = other.pop_front_entry() {
+            self.put(key, obj.get_value_copy());
+        }
+    }
+
+    /// Like `transfer`, but entries keep their frequency (see `put_with_freq`).
+    pub fn transfer_with_freq(&mut self, other: &mut S3FIFO<K, V>)
+    where K: Clone, V: Clone
+    {
+        while let Some((key, obj)) = other.pop_front_entry() {
+            self.put_with_freq(key, obj.get_value_copy(), obj.get_freq());
+        }
+    }
+
+    ///
+    /// Store `new_value` under `key` and return the value it replaced, if
+    /// any. Missing keys are inserted, same as `put`, but the key is only
+    /// cloned when an insert is needed.
+    pub fn swap(&mut self, key: &K, new_value: V) -> Option<V>
+    where K: Clone, V: Clone
+    {
+        if let Some(obj) = self.s_queue.find_mut(key) {
+            return Some(obj.replace_value(new_value));
+        }
+
+        if let Some(obj) = self.m_queue.find_mut(key) {
+            return Some(obj.replace_value(new_value));
+        }
+
+        self.insert(key.clone(), new_value);
+        None
+    }
+
+    ///
+    /// Replace the value of a cached `key` with `f(&old)`. Returns `false`,
+    /// without inserting, if the key isn't cached. This isn't counted as an
+    /// access, so the frequency is unchanged.
+    pub fn update_if_present(&mut self, key: &K, f: impl FnOnce(&V) -> V) -> bool {
+        if self.s_queue.peek(key).is_some() {
+            self.s_queue.update_if_present(key, f)
+        } else {
+            self.m_queue.update_if_present(key, f)
+        }
+    }
+
+    ///
+    /// Replace the value of `key` with `new_value` only if the cached value
+    /// equals `expected`. Returns `false` if the key is missing or the
+    /// value differs. Like `update_if_present`, this is not an access.
+    pub fn compare_and_swap(&mut self, key: &K, expected: &V, new_value: V) -> bool
+    where V: PartialEq
+    {
+        if self.s_queue.peek(key).is_some() {
+            self.s_queue.compare_and_swap(key, expected, new_value)
+        } else {
+            self.m_queue.compare_and_swap(key, expected, new_value)
+        }
+    }
+
+    ///
+    /// Clear the access frequency of a cached entry without moving it.
+    /// Returns `false` if `key` isn't in the small or main queue (ghost
+    /// entries have no frequency).
+    /// 
+    /// A main queue entry with frequency zero is evicted, rather than
+    /// reinserted, the next time the eviction pass reaches it.
+    pub fn reset_freq(&mut self, key: &K) -> bool {
+        self.s_queue.reset_freq(key) || self.m_queue.reset_freq(key)
+    }
+
+    ///
+    /// Decrement the frequency of every cached entry, so entries that were
+    /// hot long ago stop shielding themselves from eviction. Entries that
+    /// reach 0 are evicted by the next pass over their queue instead of
+    /// being promoted or reinserted.
+    ///
+    /// Call it periodically, e.g. every `10 * capacity()` operations.
+    pub fn age_all_frequencies(&mut self) {
+        self.s_queue.age_frequencies();
+        self.m_queue.age_frequencies();
+    }
+
+    ///
+    /// Remove `key` from the cache and return its value. The key is not
+    /// recorded in the ghost queue: the caller now owns the data, so a
+    /// later `put` starts it from scratch.
+    pub fn take(&mut self, key: &K) -> Option<V>
+    where K: Clone
+    {
+        let value = self.s_queue.take(key).or_else(|| self.m_queue.take(key))?;
+        self.size -= 1;
+        self.debug_assert_invariants();
+        Some(value)
+    }
+
+    ///
+    /// `take` the oldest entry of the small queue, else of the main queue,
+    /// whose value equals `value`. O(n).
+    pub fn remove_by_value(&mut self, value: &V) -> Option<(K, V)>
+    where K: Clone, V: PartialEq
+    {
+        let key = self.s_queue.find_by_value(value)
+            .or_else(|| self.m_queue.find_by_value(value))?
+            .clone();
+        let value = self.take(&key)?;
+        Some((key, value))
+    }
+
+    ///
+    /// Run `ops` in order and return one result per op. A `Remove` of a
+    /// missing key yields `BatchResult::Miss`.
+    pub fn apply_batch(
+        &mut self, 
+        ops: impl IntoIterator<Item = CacheOp<K, V>>
+    ) -> Vec<BatchResult<V>>
+    where K: Clone, V: Clone
+    {
+        let ops = ops.into_iter();
+        let mut results = Vec::with_capacity(ops.size_hint().0);
+        for op in ops {
+            let result = match op {
+                CacheOp::Get(key) => match self.get(&key) {
+                    Some(value) => BatchResult::Hit(value.clone()),
+                    None => BatchResult::Miss,
+                },
+                CacheOp::Put(key, value) => BatchResult::Inserted(self.put(key, value)),
+                CacheOp::Remove(key) => match self.take(&key) {
+                    Some(value) => BatchResult::Removed(value),
+                    None => BatchResult::Miss,
+                },
+                CacheOp::Touch(key) => BatchResult::Touched(self.get(&key).is_some()),
+            };
+            results.push(result);
+        }
+        results
+    }
+
+    ///
+    /// Return the cached value for `key`, computing and inserting it with
+    /// `value_fn` on a miss. Evicts as needed to make room.
+    pub fn get_or_evict(
+        &mut self, 
+        key: K, 
+        value_fn: impl FnOnce() -> V
+    ) -> &V
+    where K: Clone, V: Clone
+    {
+        self.get_or_compute(&key, |_| value_fn())
+    }
+
+    ///
+    /// Read-through lookup: like `get_or_evict`, but `compute` is handed
+    /// the key, so the value can be derived from it. `compute` only runs
+    /// on a miss.
+    pub fn atomic_get_put<F>(&mut self, key: K, compute: F) -> &V
+    where 
+        K: Clone, 
+        V: Clone,
+        F: FnOnce(&K) -> V,
+    {
+        self.get_or_compute(&key, compute)
+    }
+
+    /// Shared body of `get_or_else`, `get_or_evict` and `atomic_get_put`:
+    /// `get` on a hit, otherwise insert `compute(key)` and count a miss.
+    /// The key is cloned only on a miss.
+    fn get_or_compute(
+        &mut self, 
+        key: &K, 
+        compute: impl FnOnce(&K) -> V
+    ) -> &V
+    where K: Clone, V: Clone
+    {
+        // Returning the borrow from `get` in one branch and inserting in the
+        // other is rejected by the borrow checker (the borrow is considered
+        // live for the rest of the function), so check presence first and
+        // look the key up a second time.
+        if self.peek(key).is_some() {
+            return self.get(key).unwrap();
+        }
+
+        self.stats.misses += 1;
+        let value = compute(key);
+        self.insert(key.clone(), value);
+        self.peek(key).expect("key was just inserted")
+    }
+
+    ///
+    /// Sanity check: `get` `n` keys drawn uniformly from the keys cached
+    /// right now and return the observed hit rate. Anything but 1.0 means
+    /// the cache lost an entry it reports as present. `rng` is any source
+    /// of random `u64`s, as for `sample_random_key`.
+    #[cfg(not(feature = "rand"))]
+    pub fn randomized_eviction_test(&mut self, n: usize, rng: &mut impl FnMut() -> u64) -> f64
+    where K: Clone
+    {
+        let keys: Vec<K> = self.s_queue.keys().chain(self.m_queue.keys()).cloned().collect();
+        self.workload_simulation_hit_rate(&keys, n, rng)
+    }
+
+    ///
+    /// Sanity check: `get` `n` keys drawn uniformly from the keys cached
+    /// right now and return the observed hit rate. Anything but 1.0 means
+    /// the cache lost an entry it reports as present.
+    #[cfg(feature = "rand")]
+    pub fn randomized_eviction_test(&mut self, n: usize, rng: &mut impl rand::Rng) -> f64
+    where K: Clone
+    {
+        let keys: Vec<K> = self.s_queue.keys().chain(self.m_queue.keys()).cloned().collect();
+        self.workload_simulation_hit_rate(&keys, n, &mut || rng.next_u64())
+    }
+
+    ///
+    /// `get` `n` keys sampled uniformly, with replacement, from `keys` and
+    /// return the fraction that hit. Misses are not filled in, so this
+    /// measures how well the current contents serve the access pattern.
+    /// The lookups are real: they update frequencies and `stats`. Returns
+    /// 0.0 if `keys` is empty or `n` is 0.
+    pub fn workload_simulation_hit_rate(
+        &mut self, 
+        keys: &[K], 
+        n: usize, 
+        rng: &mut impl FnMut() -> u64
+    ) -> f64
+    where K: Clone
+    {
+        if keys.is_empty() || n == 0 {
+            return 0.0;
+        }
+
+        let hits = (0..n)
+            .filter(|_| {
+                let key = &keys[(rng() % keys.len() as u64) as usize];
+                self.get(key).is_some()
+            })
+            .count();
+        hits as f64 / n as f64
+    }
+
+    /// Number of cached entries (small + main queue).
+    #[doc(alias = "count")]
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Alias for [`len`](Self::len).
+    #[inline(always)]
+    pub fn count(&self) -> usize {
+        self.len()
+    }
+
+    #[inline(always)]
+    pub fn is_full(&self) -> bool {
+        self.size == self.cache_size
+    }
+
+    /// Total number of entries the cache can hold.
+    #[inline(always)]
+    pub fn capacity(&self) -> usize {
+        self.cache_size
+    }
+
+    #[inline(always)]
+    pub fn small_capacity(&self) -> usize {
+        self.small_cache_capacity
+    }
+
+    #[inline(always)]
+    pub fn main_capacity(&self) -> usize {
+        self.main_cache_capacity
+    }
+
+    /// Zero when the ghost queue is disabled.
+    #[inline(always)]
+    pub fn ghost_capacity(&self) -> usize {
+        self.ghost_cache_capacity
+    }
+
+    pub fn segment_info(&self) -> SegmentInfo {
+        SegmentInfo {
+            small_len: self.s_queue.len(),
+            small_capacity: self.small_cache_capacity,
+            main_len: self.m_queue.len(),
+            main_capacity: self.main_cache_capacity,
+            ghost_len: self.g_queue.len(),
+            ghost_capacity: self.ghost_cache_capacity,
+        }
+    }
+
+    /// Fraction of the whole cache in use.
+    #[doc(alias("load_factor", "fill_ratio"))]
+    pub fn occupancy_ratio(&self) -> f64 {
+        self.size as f64 / self.cache_size as f64
+    }
+
+    /// Alias for [`occupancy_ratio`](Self::occupancy_ratio), under the
+    /// name hash maps use.
+    #[inline(always)]
+    pub fn load_factor(&self) -> f64 {
+        self.occupancy_ratio()
+    }
+
+    /// Alias for [`occupancy_ratio`](Self::occupancy_ratio).
+    #[inline(always)]
+    pub fn fill_ratio(&self) -> f64 {
+        self.occupancy_ratio()
+    }
+
+    ///
+    /// Whether at least `threshold` of the capacity is in use, e.g. 0.9 to
+    /// start evicting ahead of demand once the cache is 90% full.
+    /// 
+    /// `threshold` must be in `(0, 1]`; debug builds panic otherwise.
+    /// Release builds clamp it: anything at or below 0 is always met,
+    /// anything above 1 (or NaN) is treated as 1, i.e. `is_full`.
+    pub fn is_almost_full(&self, threshold: f64) -> bool {
+        debug_assert!(
+            threshold > 0.0 && threshold <= 1.0,
+            "threshold must be in (0, 1], got {}", threshold
+        );
+        self.occupancy_ratio() >= threshold.min(1.0)
+    }
+
+    /// Fraction of the small queue in use.
+    pub fn small_occupancy(&self) -> f64 {
+        if self.small_cache_capacity == 0 {
+            return 0.0;
+        }
+        self.s_queue.len() as f64 / self.small_cache_capacity as f64
+    }
+
+    /// Fraction of the main queue in use.
+    pub fn main_occupancy(&self) -> f64 {
+        self.m_queue.len() as f64 / self.main_cache_capacity as f64
+    }
+
+    ///
+    /// How close the cache is to evicting, from 0.0 (empty) to 1.0 (both
+    /// queues full, so every insert evicts): `0.4 * small_occupancy +
+    /// 0.6 * main_occupancy`. Below 0.5 the cache is comfortably sized for
+    /// its load. Single-queue strategies report the main queue's
+    /// occupancy. A background task could e.g. `evict_n` above 0.9.
+    pub fn get_eviction_pressure(&self) -> f64 {
+        const SMALL_WEIGHT: f64 = 0.4;
+        const MAIN_WEIGHT: f64 = 0.6;
+
+        let pressure = if self.strategy == EvictionStrategy::S3FIFO {
+            SMALL_WEIGHT * self.small_occupancy() + MAIN_WEIGHT * self.main_occupancy()
+        } else {
+            self.main_occupancy()
+        };
+        pressure.clamp(0.0, 1.0)
+    }
+
+    /// Number of cached entries at each frequency, indexed by frequency.
+    /// Lots of entries stuck at 0 suggest the small queue is too large.
+    pub fn frequency_histogram(&self) -> [usize; MAX_FREQ + 1] {
+        let mut histogram = [0; MAX_FREQ + 1];
+        for (_, obj) in self.s_queue.iter_in_order().chain(self.m_queue.iter_in_order()) {
+            histogram[obj.get_freq()] += 1;
+        }
+        histogram
+    }
+
+    ///
+    /// Up to `k` cached keys with the highest frequency, hottest first.
+    /// Ties go to the entry closer to the front of the queues (small queue
+    /// first). O(n log k).
+    pub fn top_k_by_freq(&self, k: usize) -> Vec<(&K, usize)> {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        let entries = self.s_queue.iter_in_order().chain(self.m_queue.iter_in_order());
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for (position, (key, obj)) in entries.enumerate() {
+            heap.push(Reverse(Ranked { freq: obj.get_freq(), position, key }));
+            if heap.len() > k {
+                heap.pop();
+            }
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(ranked)| (ranked.key, ranked.freq))
+            .collect()
+    }
+
+    #[inline(always)]
+    pub fn freq_promote_threshold(&self) -> usize {
+        self.freq_promote_threshold
+    }
+
+    #[inline(always)]
+    pub fn ghost_enabled(&self) -> bool {
+        self.ghost_enabled
+    }
+
+    #[inline(always)]
+    pub fn strategy(&self) -> EvictionStrategy {
+        self.strategy
+    }
+
+    /// `None` unless the cache was built `with_eviction_log`.
+    #[inline(always)]
+    pub fn eviction_log(&self) -> Option<&EvictionLog<K>> {
+        self.eviction_log.as_ref()
+    }
+
+    #[inline(always)]
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// Print `stats` on one line to stdout.
+    pub fn print_stats(&self) {
+        println!("{}", self.stats);
+    }
+
+    /// A view that can look entries up but not change the cache, not even
+    /// its frequencies or stats.
+    #[inline(always)]
+    pub fn as_read_only(&self) -> ReadOnlyS3FIFO<'_, K, V> {
+        ReadOnlyS3FIFO(self)
+    }
+
+    ///
+    /// A multi-line summary of queue fill levels, the frequency histogram
+    /// and hit rates, for logging while debugging a cache's sizing.
+    #[cold]
+    pub fn capacity_utilization_report(&self) -> String {
+        let mut report = String::new();
+        let info = self.segment_info();
+        let rows = [
+            ("total", self.size, self.cache_size, self.occupancy_ratio()),
+            ("small", info.small_len, info.small_capacity, self.small_occupancy()),
+            ("main", info.main_len, info.main_capacity, self.main_occupancy()),
+        ];
+        for (name, len, capacity, ratio) in rows {
+            let _ = writeln!(report, "{:<6} {:>8} / {:<8} {:>6.1}%", name, len, capacity, ratio * 100.0);
+        }
+        let _ = writeln!(report, "{:<6} {:>8} / {}", "ghost", info.ghost_len, info.ghost_capacity);
+
+        let histogram = self.frequency_histogram()
+            .iter()
+            .enumerate()
+            .map(|(freq, count)| format!("{}:{}", freq, count))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let _ = writeln!(report, "{:<6} {}", "freq", histogram);
+
+        let stats = &self.stats;
+        let _ = writeln!(report, "{:<6} {:>8} {:>17.1}%", "hits", stats.hits, stats.hit_rate() * 100.0);
+        let _ = write!(report, "{:<6} {:>8}", "misses", stats.misses);
+        report
+    }
+
+    ///
+    /// The queues as a Graphviz DOT graph: one cluster per queue, nodes
+    /// labelled with the key and frequency, edges from older to newer
+    /// entries. Ghost nodes are dashed. Render with e.g. `dot -Tsvg`.
+    #[cold]
+    pub fn print_dot(&self) -> String
+    where K: Debug
+    {
+        let live = |queue: &FIFOCache<K, V>| -> Vec<String> {
+            queue.iter_in_order()
+                .map(|(key, obj)| format!("{:?} (freq {})", key, obj.get_freq()))
+                .collect()
+        };
+        let ghosts: Vec<String> = self.g_queue.keys().map(|key| format!("{:?}", key)).collect();
+
+        let mut dot = String::new();
+        let _ = writeln!(dot, "digraph S3FIFO {{");
+        let _ = writeln!(dot, "    rankdir=LR;");
+        let _ = writeln!(dot, "    node [shape=box];");
+        Self::write_dot_cluster(&mut dot, "small", &live(&self.s_queue), "solid");
+        Self::write_dot_cluster(&mut dot, "main", &live(&self.m_queue), "solid");
+        Self::write_dot_cluster(&mut dot, "ghost", &ghosts, "dashed");
+        let _ = write!(dot, "}}");
+        dot
+    }
+
+    fn write_dot_cluster(dot: &mut String, name: &str, labels: &[String], style: &str) {
+        let _ = writeln!(dot, "    subgraph cluster_{} {{", name);
+        let _ = writeln!(dot, "        label=\"{}\";", name);
+        for (i, label) in labels.iter().enumerate() {
+            // `{:?}` quotes the label and escapes any quotes in the key.
+            let _ = writeln!(dot, "        {}_{} [label={:?}, style={}];", name, i, label, style);
+        }
+        for i in 1..labels.len() {
+            let _ = writeln!(dot, "        {}_{} -> {}_{};", name, i - 1, name, i);
+        }
+        let _ = writeln!(dot, "    }}");
+    }
+
+    /// Keys of the small, main and ghost queues, in that order.
+    /// Ghost keys may no longer be cached, so the result may contain keys
+    /// that `get` will not find. A key that came back through a ghost hit
+    /// shows up twice until its ghost slot ages out.
+    pub fn all_keys(&self) -> impl Iterator<Item = &K> {
+        self.s_queue.keys()
+            .chain(self.m_queue.keys())
+            .chain(self.g_queue.keys())
+    }
+
+    /// Keys of the ghost queue in FIFO order, the next to be forgotten
+    /// first. A `put` of one of them is a ghost hit and, unless the
+    /// admission sketch turns it away, enters the main queue.
+    pub fn iter_ghost_keys(&self) -> impl Iterator<Item = &K> {
+        self.g_queue.keys()
+    }
+
+    /// Whether a `put` of `key` would be a ghost hit. O(1).
+    #[inline(always)]
+    pub fn is_in_ghost(&self, key: &K) -> bool {
+        self.ghost_enabled && self.g_queue.peek(key).is_some()
+    }
+
+    ///
+    /// Call `f` on every cached entry, e.g. to mark values stale in place.
+    /// Keys can't be changed and nothing is removed (see `partition_by`
+    /// for that); frequencies and stats are left alone. The order is
+    /// unspecified.
+    pub fn for_each_mut<F>(&mut self, mut f: F)
+    where F: FnMut(&K, &mut V)
+    {
+        self.s_queue.for_each_mut(&mut f);
+        self.m_queue.for_each_mut(&mut f);
+    }
+
+    /// Call `f` on every cached entry, small queue first, each queue in
+    /// FIFO order.
+    pub fn for_each<F>(&self, mut f: F)
+    where F: FnMut(&K, &V)
+    {
+        for (key, value) in self.s_queue.items().chain(self.m_queue.items()) {
+            f(key, value);
+        }
+    }
+
+    /// Values of the small and main queues, in that order.
+    pub fn all_values(&self) -> impl Iterator<Item = &V> {
+        self.s_queue.values().chain(self.m_queue.values())
+    }
+
+    ///
+    /// Consume the cache, keeping only the main queue's (hot) entries, in
+    /// FIFO order. Useful to persist what's worth keeping on shutdown.
+    pub fn into_main_entries(mut self) -> Vec<(K, V)>
+    where K: Clone
+    {
+        Self::drain_queue(&mut self.m_queue)
+    }
+
+    /// Consume the cache, keeping only the small queue's entries, in FIFO order.
+    pub fn into_small_entries(mut self) -> Vec<(K, V)>
+    where K: Clone
+    {
+        Self::drain_queue(&mut self.s_queue)
+    }
+
+    /// Copies of the main queue's entries, in FIFO order.
+    pub fn main_entries_cloned(&self) -> Vec<(K, V)>
+    where K: Clone, V: Clone
+    {
+        self.m_queue.items().map(|(key, value)| (key.clone(), value.clone())).collect()
+    }
+
+    /// Copies of the small queue's entries, in FIFO order.
+    pub fn small_entries_cloned(&self) -> Vec<(K, V)>
+    where K: Clone, V: Clone
+    {
+        self.s_queue.items().map(|(key, value)| (key.clone(), value.clone())).collect()
+    }
+
+    ///
+    /// Consume the cache, returning every cached entry as
+    /// `(key, value, freq)`, hottest first. Equal frequencies keep their
+    /// FIFO position: small queue first, older entries first. Handy to keep
+    /// only the hottest N entries under memory pressure.
+    pub fn into_sorted_by_freq(mut self) -> Vec<(K, V, usize)>
+    where K: Clone
+    {
+        let mut entries = Vec::with_capacity(self.size);
+        for queue in [&mut self.s_queue, &mut self.m_queue] {
+            while let Some((key, obj)) = queue.evict() {
+                let freq = obj.get_freq();
+                entries.push((key, obj.into_value(), freq));
+            }
+        }
+        entries.sort_by_key(|&(_, _, freq)| Reverse(freq));
+        entries
+    }
+
+    /// Copies of every cached entry, ordered like `into_sorted_by_freq`.
+    pub fn sorted_by_freq_cloned(&self) -> Vec<(K, V, usize)>
+    where K: Clone, V: Clone
+    {
+        let mut entries = self.snapshot();
+        entries.sort_by_key(|&(_, _, freq)| Reverse(freq));
+        entries
+    }
+
+    ///
+    /// Empty the cache, returning `(main, small)`: the main queue's (hot)
+    /// entries and the small queue's, each in FIFO order. The ghost queue
+    /// is cleared too. To re-warm a cache after a restart, hand both back
+    /// to `insert_batch_with_priority`, which puts `main` straight into
+    /// the main queue.
+    #[allow(clippy::type_complexity)]
+    pub fn drain_to_vec_with_priority(&mut self) -> (Vec<(K, V)>, Vec<(K, V)>)
+    where K: Clone
+    {
+        let main = Self::drain_queue(&mut self.m_queue);
+        let small = Self::drain_queue(&mut self.s_queue);
+        // `evict` yields nothing for a duplicate slot, so don't stop there.
+        while !self.g_queue.is_empty() {
+            self.g_queue.evict();
+        }
+        // Also drops ghost keys whose slot was overwritten.
+        self.g_queue.collect_garbage();
+        self.size = 0;
+        self.debug_assert_invariants();
+        (main, small)
+    }
+
+    fn drain_queue(queue: &mut FIFOCache<K, V>) -> Vec<(K, V)>
+    where K: Clone
+    {
+        std::iter::from_fn(|| queue.evict())
+            .map(|(key, obj)| (key, obj.into_value()))
+            .collect()
+    }
+
+    ///
+    /// An owned copy of every cached entry as `(key, value, freq)`, small
+    /// queue first, each queue in FIFO order. The ghost queue is not
+    /// included. Feed it back through `restore_from_snapshot`.
+    pub fn snapshot(&self) -> Vec<(K, V, usize)>
+    where K: Clone, V: Clone
+    {
+        self.s_queue.iter_in_order()
+            .chain(self.m_queue.iter_in_order())
+            .map(|(key, obj)| (key.clone(), obj.get_value().clone(), obj.get_freq()))
+            .collect()
+    }
+
+    ///
+    /// Compare the cached key-value pairs of `self` and `other`, e.g. to
+    /// check a snapshot round trip. Keys are listed small queue first.
+    pub fn diff(&self, other: &S3FIFO<K, V>) -> CacheDiff<K, V>
+    where K: Clone, V: Clone + PartialEq
+    {
+        let mut diff = CacheDiff {
+            only_in_self: Vec::new(),
+            only_in_other: Vec::new(),
+            changed: Vec::new(),
+        };
+
+        for (key, value) in self.s_queue.items().chain(self.m_queue.items()) {
+            match other.peek(key) {
+                None => diff.only_in_self.push(key.clone()),
+                Some(theirs) if theirs != value => {
+                    diff.changed.push((key.clone(), value.clone(), theirs.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for key in other.s_queue.keys().chain(other.m_queue.keys()) {
+            if self.peek(key).is_none() {
+                diff.only_in_other.push(key.clone());
+            }
+        }
+        diff
+    }
+
+    /// Cached entries for which `predicate` holds, small queue first.
+    /// Frequencies are left untouched.
+    pub fn get_all_matching<P>(&self, predicate: P) -> Vec<(&K, &V)>
+    where P: Fn(&K, &V) -> bool
+    {
+        self.s_queue.items()
+            .chain(self.m_queue.items())
+            .filter(|(key, value)| predicate(key, value))
+            .collect()
+    }
+
+    ///
+    /// A copy of the cache with every value mapped through `f`. Capacities,
+    /// configuration, queue membership and order, frequencies, the ghost
+    /// queue and stats all carry over.
+    pub fn map_values<W, F>(&self, f: F) -> S3FIFO<K, W>
+    where 
+        K: Default + Clone,
+        W: Default + Clone,
+        F: Fn(&V) -> W,
+    {
+        let map_queue = |queue: &FIFOCache<K, V>| {
+            let mut mapped = FIFOCache::new(queue.capacity());
+            for (key, obj) in queue.iter_in_order() {
+                mapped.insert_with_meta(key.clone(), f(obj.get_value()), obj.get_meta_copy());
+            }
+            mapped
+        };
+
+        S3FIFO {
+            cache_size: self.cache_size,
+            small_cache_capacity_ratio: self.small_cache_capacity_ratio,
+            small_cache_capacity: self.small_cache_capacity,
+            main_cache_capacity: self.main_cache_capacity,
+            ghost_cache_capacity: self.ghost_cache_capacity,
+            s_queue: map_queue(&self.s_queue),
+            m_queue: map_queue(&self.m_queue),
+            g_queue: self.g_queue.clone(),
+            ghost_enabled: self.ghost_enabled,
+            freq_promote_threshold: self.freq_promote_threshold,
+            policy: Arc::clone(&self.policy),
+            strategy: self.strategy,
+            last_hit_rate_sample: self.last_hit_rate_sample,
+            eviction_log: self.eviction_log.clone(),
+            #[cfg(feature = "admission_sketch")]
+            sketch: self.sketch.clone(),
+            size: self.size,
+            stats: self.stats.clone(),
+        }
+    }
+
+    ///
+    /// A copy of the cache with every key, ghost keys included, mapped
+    /// through `f`, e.g. to switch to pre-hashed keys. Values, frequencies,
+    /// queue membership and order, configuration and stats carry over.
+    ///
+    /// If `f` maps several cached keys to the same key, only the one met
+    /// last (small queue first, each queue oldest first) is kept; the
+    /// others are silently dropped. A ghost key colliding with a cached
+    /// key is dropped too.
+    pub fn map_keys<J, F>(&self, f: F) -> S3FIFO<J, V>
+    where 
+        K: Clone,
+        J: Default + Clone + Eq + Hash,
+        V: Default + Clone,
+        F: Fn(K) -> J,
+    {
+        let mut s_queue = FIFOCache::new(self.s_queue.capacity());
+        let mut m_queue = FIFOCache::new(self.m_queue.capacity());
+        for (in_small, queue) in [(true, &self.s_queue), (false, &self.m_queue)] {
+            for (key, obj) in queue.iter_in_order() {
+                let key = f(key.clone());
+                if s_queue.take(&key).is_none() {
+                    m_queue.take(&key);
+                }
+                let target = if in_small { &mut s_queue } else { &mut m_queue };
+                target.insert_with_meta(key, obj.get_value().clone(), obj.get_meta_copy());
+            }
+        }
+
+        let mut g_queue = FIFOCache::new(self.g_queue.capacity());
+        for key in self.g_queue.keys() {
+            let key = f(key.clone());
+            let taken = s_queue.peek(&key).is_some()
+                || m_queue.peek(&key).is_some()
+                || g_queue.peek(&key).is_some();
+            if !taken {
+                g_queue.insert(key, ());
+            }
+        }
+
+        S3FIFO {
+            cache_size: self.cache_size,
+            small_cache_capacity_ratio: self.small_cache_capacity_ratio,
+            small_cache_capacity: self.small_cache_capacity,
+            main_cache_capacity: self.main_cache_capacity,
+            ghost_cache_capacity: self.ghost_cache_capacity,
+            size: s_queue.len() + m_queue.len(),
+            s_queue,
+            m_queue,
+            g_queue,
+            ghost_enabled: self.ghost_enabled,
+            freq_promote_threshold: self.freq_promote_threshold,
+            policy: Arc::clone(&self.policy),
+            strategy: self.strategy,
+            last_hit_rate_sample: self.last_hit_rate_sample,
+            eviction_log: self.eviction_log.as_ref().map(|log| {
+                let mut mapped = EvictionLog::new(log.max_size());
+                log.iter().for_each(|key| mapped.record(f(key.clone())));
+                mapped
+            }),
+            // Counts can't be carried over to different keys.
+            #[cfg(feature = "admission_sketch")]
+            sketch: FrequencySketch::new(self.cache_size),
+            stats: self.stats.clone(),
+        }
+    }
+
+    ///
+    /// Remove the entries for which `predicate` holds and hand them back,
+    /// small queue first, each queue in FIFO order. The rest stay cached
+    /// in place. Removed keys are not recorded in the ghost queue.
+    pub fn partition_by<P>(&mut self, mut predicate: P) -> Vec<(K, V)>
+    where 
+        K: Clone,
+        P: FnMut(&K, &V) -> bool,
+    {
+        let mut removed = self.s_queue.extract_if(&mut predicate);
+        removed.extend(self.m_queue.extract_if(&mut predicate));
+        self.size -= removed.len();
+        self.debug_assert_invariants();
+        removed
+    }
+
+    /// Like `get_all_matching`, but only counts the matches.
+    pub fn count_matching<P>(&self, predicate: P) -> usize
+    where P: Fn(&K, &V) -> bool
+    {
+        self.s_queue.items()
+            .chain(self.m_queue.items())
+            .filter(|(key, value)| predicate(key, value))
+            .count()
+    }
+
+    ///
+    /// Cached entries in the order they are expected to be evicted: small
+    /// queue entries that won't be promoted, then the main queue.
+    /// 
+    /// The order is advisory. A later `get` or `put` changes frequencies,
+    /// and main queue entries with a non-zero frequency get reinserted
+    /// instead of evicted.
+    pub fn iter_in_eviction_order(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.s_queue.iter_in_order()
+            .filter(|(_, obj)| !self.promotes(obj.get_freq()))
+            .chain(self.m_queue.iter_in_order())
+            .map(|(key, obj)| (key, obj.get_value()))
+    }
+
+    ///
+    /// The key the next eviction (e.g. `evict_n(1)`) will pick, without
+    /// evicting it: the oldest small queue entry that won't be promoted,
+    /// or else the main queue entry whose frequency runs out first,
+    /// counting the small queue entries promoted on the way.
+    ///
+    /// This is an approximation: any later access changes frequencies, and
+    /// if promotions ahead of the candidate overflow the main queue, a main
+    /// queue entry goes first.
+    pub fn nearest_eviction_candidate(&self) -> Option<&K> {
+        let from_small = self.s_queue.iter_in_order()
+            .find(|(_, obj)| !self.promotes(obj.get_freq()))
+            .map(|(key, _)| key);
+        if from_small.is_some() {
+            return from_small;
+        }
+
+        match self.strategy {
+            EvictionStrategy::MRU => self.m_queue.keys().last(),
+            EvictionStrategy::PureFIFO | EvictionStrategy::LRU => self.m_queue.keys().next(),
+            // Every small queue entry gets promoted first, joining the back
+            // of the main queue with frequency 0. Each pass over the main
+            // queue then decrements every frequency by one.
+            EvictionStrategy::S3FIFO => self.m_queue.iter_in_order()
+                .map(|(key, obj)| (key, obj.get_freq()))
+                .chain(self.s_queue.keys().map(|key| (key, 0)))
+                .enumerate()
+                .min_by_key(|&(position, (_, freq))| (freq, position))
+                .map(|(_, (key, _))| key),
+        }
+    }
+
+    ///
+    /// Roughly the least recently used key: the front of the small queue,
+    /// or of the main queue if the small one is empty. S3-FIFO doesn't
+    /// track recency, so this is the oldest insertion rather than the
+    /// oldest access. O(1).
+    pub fn get_lru_approximation(&self) -> Option<&K> {
+        self.s_queue.item_at(0)
+            .or_else(|| self.m_queue.item_at(0))
+            .map(|(key, _)| key)
+    }
+
+    ///
+    /// Roughly the most recently used key: the tail of the main queue, the
+    /// entry promoted or reinserted last, or of the small queue if the main
+    /// one is empty. Like `get_lru_approximation`, not a recency scan. O(1).
+    pub fn get_mru_approximation(&self) -> Option<&K> {
+        let queue = if self.m_queue.is_empty() { &self.s_queue } else { &self.m_queue };
+        queue.len()
+            .checked_sub(1)
+            .and_then(|index| queue.item_at(index))
+            .map(|(key, _)| key)
+    }
+
+    /// The first `k` keys of `iter_in_eviction_order`, closest to eviction
+    /// first.
+    pub fn get_k_lru_candidates(&self, k: usize) -> Vec<&K> {
+        self.iter_in_eviction_order().map(|(key, _)| key).take(k).collect()
+    }
+
+    ///
+    /// A uniformly chosen cached key (small or main queue, never ghost),
+    /// or `None` if the cache is empty. `rng` is any source of random
+    /// `u64`s; enable the `rand` feature to pass a `rand::Rng` instead.
+    #[cfg(not(feature = "rand"))]
+    pub fn sample_random_key(&self, rng: &mut impl FnMut() -> u64) -> Option<&K> {
+        self.sample_random_entry(rng).map(|(key, _)| key)
+    }
+
+    /// Like `sample_random_key`, but returns the value too.
+    #[cfg(not(feature = "rand"))]
+    pub fn sample_random_entry(&self, rng: &mut impl FnMut() -> u64) -> Option<(&K, &V)> {
+        self.entry_at_random(|total| (rng() % total as u64) as usize)
+    }
+
+    ///
+    /// A uniformly chosen cached key (small or main queue, never ghost),
+    /// or `None` if the cache is empty.
+    #[cfg(feature = "rand")]
+    pub fn sample_random_key(&self, rng: &mut impl rand::Rng) -> Option<&K> {
+        self.sample_random_entry(rng).map(|(key, _)| key)
+    }
+
+    /// Like `sample_random_key`, but returns the value too.
+    #[cfg(feature = "rand")]
+    pub fn sample_random_entry(&self, rng: &mut impl rand::Rng) -> Option<(&K, &V)> {
+        self.entry_at_random(|total| rng.random_range(0..total))
+    }
+
+    /// The cached entry at `pick(total)`, counting the small queue first.
+    fn entry_at_random(&self, pick: impl FnOnce(usize) -> usize) -> Option<(&K, &V)> {
+        let total = self.s_queue.len() + self.m_queue.len();
+        if total == 0 {
+            return None;
+        }
+
+        let index = pick(total);
+        if index < self.s_queue.len() {
+            self.s_queue.item_at(index)
+        } else {
+            self.m_queue.item_at(index - self.s_queue.len())
+        }
+    }
+
+    ///
+    /// Position of `key` in its queue (small or main), counted from the
+    /// front: 0 is the next entry that queue examines for eviction. `None`
+    /// if `key` isn't cached. Doesn't count as an access. O(n) in the
+    /// queue length.
+    pub fn get_insertion_order(&self, key: &K) -> Option<usize> {
+        self.s_queue.position_of(key).or_else(|| self.m_queue.position_of(key))
+    }
+
+    ///
+    /// How close `key` is to eviction, same as `get_insertion_order`. A
+    /// small distance means the entry is at risk unless it is read again;
+    /// under the `MRU` strategy the main queue is evicted from the back.
+    #[inline(always)]
+    pub fn eviction_distance(&self, key: &K) -> Option<usize> {
+        self.get_insertion_order(key)
+    }
+
+    ///
+    /// Estimated number of insertions before `key` is evicted, taken to be
+    /// its `eviction_distance`. That assumes every insertion removes one
+    /// entry from the front of `key`'s own queue. Insertions that land
+    /// while the cache still has room, or that evict from the other queue,
+    /// make it longer; one eviction pass that promotes several hot entries
+    /// at once makes it shorter. A read can save `key` altogether.
+    #[inline(always)]
+    pub fn time_to_eviction(&self, key: &K) -> Option<usize> {
+        self.eviction_distance(key)
+    }
+
+    ///
+    /// Up to `n` keys on each side of `key` in its queue's FIFO order: the
+    /// older keys, which are evicted first, then the newer ones. Both are
+    /// empty if `key` isn't cached. Ghost keys are never included. O(n) in
+    /// the queue length, meant for inspection.
+    pub fn get_neighbor_keys(&self, key: &K, n: usize) -> (Vec<&K>, Vec<&K>) {
+        self.s_queue.neighbor_keys(key, n)
+            .or_else(|| self.m_queue.neighbor_keys(key, n))
+            .unwrap_or_default()
+    }
+
+    /// Release the ghost queue's spare hash table capacity and ring buffer
+    /// slots, see `shrink_to_fit`.
+    pub fn shrink_ghost(&mut self)
+    where K: Default
+    {
+        self.g_queue.shrink_to_fit();
+    }
+
+    ///
+    /// Bring the ghost queue's ring buffer and hash table back in sync, see
+    /// `FIFOCache::collect_garbage`. A key evicted to the ghost queue twice
+    /// takes up two slots, and a stale hash table entry keeps counting as
+    /// a ghost hit. Returns how many stale entries were dropped.
+    pub fn collect_garbage(&mut self) -> usize
+    where K: Clone
+    {
+        self.g_queue.collect_garbage()
+    }
+
+    ///
+    /// Release memory the queues allocated but no longer use, e.g. after a
+    /// burst of `take`s: spare hash table capacity and ring buffer slots.
+    /// Capacities, and so eviction, are unchanged; the ring buffers grow
+    /// back as entries are inserted.
+    pub fn shrink_to_fit(&mut self)
+    where K: Default
+    {
+        self.s_queue.shrink_to_fit();
+        self.m_queue.shrink_to_fit();
+        self.g_queue.shrink_to_fit();
+    }
+
+    /// Release spare hash table capacity in all queues and rotate their
+    /// ring buffers to start at index 0. Queue capacities are unchanged.
+    pub fn compact(&mut self) {
+        self.s_queue.compact();
+        self.m_queue.compact();
+        self.g_queue.compact();
+    }
+
+    /// Rough number of bytes allocated across all queues but not holding
+    /// an entry.
+    pub fn memory_overhead_estimate(&self) -> usize {
+        self.s_queue.memory_overhead_estimate()
+            + self.m_queue.memory_overhead_estimate()
+            + self.g_queue.memory_overhead_estimate()
+    }
+
+    ///
+    /// Rough number of bytes the cache holds on the heap: cached entries,
+    /// ghost keys and the ring buffers backing each queue.
+    /// 
+    /// Only `size_of` is counted, so memory owned by keys or values (the
+    /// contents of a `String`, say) is not included, and neither is hash
+    /// table overhead. Treat the result as a lower bound.
+    pub fn mem_usage_bytes(&self) -> usize {
+        let entry = size_of::<K>() + size_of::<V>() + size_of::<CacheMetadata>();
+        let entries = (self.s_queue.len() + self.m_queue.len()) * entry;
+        let ghosts = self.g_queue.len() * size_of::<K>();
+        let ring_buffers = (self.s_queue.allocated_slots() 
+            + self.m_queue.allocated_slots() 
+            + self.g_queue.allocated_slots()) * size_of::<K>();
+        entries + ghosts + ring_buffers
+    }
+
+    ///
+    /// Panics if the internal bookkeeping is inconsistent: `len` must match
+    /// the queues, the small and main queues' ring buffers and hash tables
+    /// must hold the same keys, and no key may be in both of them.
+    /// 
+    /// Debug builds run this after every insertion and removal.
+    pub fn assert_invariants(&self) {
+        let (small, main) = (self.s_queue.len(), self.m_queue.len());
+        assert_eq!(
+            self.size, small + main, 
+            "size is {} but the small and main queues hold {} and {} entries", 
+            self.size, small, main
+        );
+        self.s_queue.assert_invariants("small");
+        self.m_queue.assert_invariants("main");
+        assert!(
+            !self.s_queue.keys().any(|key| self.m_queue.peek(key).is_some()),
+            "a key is cached in both the small and main queue"
+        );
+    }
+
+    #[inline(always)]
+    fn debug_assert_invariants(&self) {
+        #[cfg(debug_assertions)]
+        self.assert_invariants();
+    }
+
+    /// Look up `key` without touching frequencies or stats.
+    #[inline(always)]
+    pub(crate) fn peek(&self, key: &K) -> Option<&V> {
+        self.s_queue.peek(key)
+            .or_else(|| self.m_queue.peek(key))
+            .map(|obj| obj.get_value())
+    }
+
+    #[inline(always)]
+    pub(crate) fn peek_mut(&mut self, key: &K) -> Option<&mut V> {
+        if let Some(obj) = self.s_queue.peek_mut(key) {
+            return Some(obj.get_value_mut());
+        }
+        self.m_queue.peek_mut(key).map(|obj| obj.get_value_mut())
+    }
+
+    /// The key at position `idx` of the small queue, oldest first.
+    #[cfg(test)]
+    pub(crate) fn small_queue_key_at(&self, idx: usize) -> Option<&K> {
+        self.s_queue.item_at(idx).map(|(key, _)| key)
+    }
+
+    /// The key at position `idx` of the main queue, oldest first.
+    #[cfg(test)]
+    pub(crate) fn main_queue_key_at(&self, idx: usize) -> Option<&K> {
+        self.m_queue.item_at(idx).map(|(key, _)| key)
+    }
+}
+
+/// 
+/// Some internal functions
+/// They are develoepr-facing APIs.
+impl<K, V> S3FIFO<K, V> 
+where 
+    K: Clone + Eq + Hash, 
+    V: Clone
+{
+    #[inline(always)]
+    pub(crate) fn insert(&mut self, key: K, value: V)
+    {
+        self.insert_with_meta(key, value, CacheMetadata::default())
+    }
+
+    fn insert_with_meta(
+        &mut self, 
+        key: K, 
+        value: V, 
+        meta: CacheMetadata
+    )
+    {
+        #[cfg(feature = "admission_sketch")]
+        self.sketch.increment(&key);
+        // Decide before making room, which may free the very main queue
+        // slot the admission check is about.
+        let admitted = self.admit_to_main(&key);
+        self.make_room();
+
+        if !self.ghost_enabled {
+            self.push_small(key, value, meta);
+        } else if self.g_queue.peek(&key).is_some() {
+            // Found in ghost queue. The ghost slot is left to age out:
+            // removing it would be O(n), and `retire_small` won't add a
+            // second one for the same key.
+            self.stats.ghost_hits += 1;
+            if admitted {
+                self.push_main(key, value, meta);
+            } else {
+                self.push_small(key, value, meta);
+            }
+        } else {
+            self.stats.ghost_misses += 1;
+            self.push_small(key, value, meta);
+        }
+    }
+
+    /// Whether a ghost hit on `key` may enter the main queue. A full main
+    /// queue only takes it if it has been seen more often than the entry
+    /// it would push out.
+    #[cfg(feature = "admission_sketch")]
+    fn admit_to_main(&self, key: &K) -> bool {
+        match self.m_queue.item_at(0) {
+            Some((victim, _)) if self.m_queue.is_full() => {
+                self.sketch.estimate(key) > self.sketch.estimate(victim)
+            }
+            _ => true,
+        }
+    }
+
+    #[cfg(not(feature = "admission_sketch"))]
+    #[inline(always)]
+    fn admit_to_main(&self, _key: &K) -> bool {
+        true
+    }
+
+    ///
+    /// Evict until there is at least one free slot. Every round of `evict`
+    /// frees one unless the size bookkeeping is off, which debug builds
+    /// catch here; release builds give up instead of looping forever and
+    /// let the queues make room on insert.
+    fn make_room(&mut self) {
+        let max_iterations = self.cache_size * 2;
+        let mut iterations = 0;
+        while self.is_full() && iterations < max_iterations {
+            self.evict();
+            iterations += 1;
+        }
+        debug_assert!(
+            !self.is_full(),
+            "eviction made no progress after {} iterations", iterations
+        );
+    }
+
+    /// Append a new entry to the small queue. Callers must `make_room` first.
+    #[inline(always)]
+    fn push_small(&mut self, key: K, value: V, meta: CacheMetadata) {
+        // Single-queue strategies keep everything in the main queue.
+        if self.strategy != EvictionStrategy::S3FIFO {
+            return self.push_main(key, value, meta);
+        }
+        if self.s_queue.is_full() { self.evict_s() }
+        self.s_queue.insert_with_meta(key, value, meta);
+        self.size += 1;
+        self.debug_assert_invariants();
+    }
+
+    /// Append a new entry to the main queue. Callers must `make_room` first.
+    #[inline(always)]
+    fn push_main(&mut self, key: K, value: V, meta: CacheMetadata) {
+        if self.m_queue.is_full() { self.evict_m() }
+        self.m_queue.insert_with_meta(key, value, meta);
+        self.size += 1;
+        self.debug_assert_invariants();
+    }
+
+    /// Place `(key, value, freq)` entries by frequency, spilling cold ones
+    /// into the main queue once the small queue is full.
+    fn restore_entries(
+        &mut self, 
+        entries: impl IntoIterator<Item = (K, V, usize)>
+    ) {
+        for (key, value, freq) in entries {
+            if self.peek(&key).is_some() {
+                self.put_with_freq(key, value, freq);
+                continue;
+            }
+
+            self.make_room();
+            let meta = CacheMetadata::with_freq(freq);
+            if self.promotes(freq) || self.s_queue.is_full() {
+                self.push_main(key, value, meta);
+            } else {
+                self.push_small(key, value, meta);
+            }
+        }
+    }
+
+    /// Remove the oldest entry of the small queue, or of the main queue once
+    /// the small queue is empty, bypassing the eviction policy.
+    fn pop_front_entry(&mut self) -> Option<(K, CacheObject<V>)> {
+        let entry = self.s_queue.evict().or_else(|| self.m_queue.evict())?;
+        self.size -= 1;
+        self.debug_assert_invariants();
+        Some(entry)
+    }
+
+    ///
+    /// Evict `n` entries, or all of them if there are fewer, and return how
+    /// many were actually evicted. Promoting a small queue entry into a
+    /// full main queue evicts from the main queue on the way, so this can
+    /// be one more than `n`.
+    pub fn evict_n(&mut self, n: usize) -> usize {
+        let mut evicted = 0;
+        while evicted < n {
+            let step = self.evict_one();
+            if step == 0 {
+                break;
+            }
+            evicted += step;
+        }
+        self.debug_assert_invariants();
+        evicted
+    }
+
+    ///
+    /// Evict every entry whose frequency is below `min_freq`: 1 drops the
+    /// entries never read since insertion, 2 keeps only hot ones. Small
+    /// queue entries go to the ghost queue as with normal eviction; main
+    /// queue entries are dropped outright. Returns how many were evicted.
+    pub fn discard_cold_entries(&mut self, min_freq: usize) -> usize {
+        let cold = |_: &K, obj: &CacheObject<V>| obj.get_freq() < min_freq;
+        let small = self.s_queue.drain_where(cold);
+        let main = self.m_queue.drain_where(cold);
+        let discarded = small.len() + main.len();
+
+        for (key, obj) in small {
+            self.retire_small(key, &obj);
+        }
+        self.stats.full_evictions += main.len() as u64;
+        self.stats.main_evictions += main.len() as u64;
+        if let Some(log) = self.eviction_log.as_mut() {
+            for (key, _) in main {
+                log.record(key);
+            }
+        }
+        self.size -= discarded;
+        self.debug_assert_invariants();
+        discarded
+    }
+
+    /// Evict an entry, preferring the small queue, and return how many
+    /// were evicted: 0 if the cache is empty, 2 if a promotion overflowed
+    /// the main queue.
+    fn evict_one(&mut self) -> usize {
+        let before = self.size;
+        if !self.s_queue.is_empty() {
+            self.evict_s();
+        }
+        // The small queue may only have promoted entries.
+        if self.size == before && !self.m_queue.is_empty() {
+            self.evict_m();
+        }
+        before - self.size
+    }
+
+    #[inline(always)]
+    fn evict(&mut self) {
+        if self.s_queue.is_full() {
+            self.evict_s();
+        }
+
+        if self.m_queue.is_full() {
+            self.evict_m();
+        }
+    }
+
+    #[inline(always)]
+    fn evict_s(&mut self) 
+    {
+        // Stop after one full pass: if every entry was promoted there is
+        // nothing left to evict here, and `evict_m` has made room instead.
+        let pass = self.s_queue.len();
+        let mut visited = 0;
+        let mut evicted = false;
+        while !evicted && visited < pass && !self.s_queue.is_empty() {
+            visited += 1;
+            if let Some((key, obj)) = self.s_queue.evict() {
+                if self.promotes(obj.get_freq()) {
+                    if self.m_queue.is_full() { self.evict_m() }
+                    self.m_queue.insert(key, obj.get_value_copy());
+                    self.stats.promotions_to_main += 1;
+                } else {
+                    self.retire_small(key, &obj);
+                    self.size -= 1;
+                    evicted = true;
+                }
+            }
+        }
+    }
+
+    /// Bookkeeping for an entry evicted from the small queue: remember it
+    /// in the ghost queue, unless it was prefetched and never read, in
+    /// which case it is fully evicted and logged.
+    fn retire_small(&mut self, key: K, obj: &CacheObject<V>) {
+        let never_read = obj.get_meta().is_prefetched() && obj.get_freq() == 0;
+        self.stats.small_evictions += 1;
+        if self.ghost_enabled && !never_read {
+            // A key can come back to the small queue without a ghost hit,
+            // e.g. through `warm_from_iter`; keep its existing ghost slot.
+            if self.g_queue.peek(&key).is_none() {
+                // Make room first: a full ring buffer would overwrite its
+                // oldest key but leave it in the hash table.
+                if self.g_queue.is_full() { self.g_queue.evict(); }
+                self.g_queue.insert(key, ());
+            }
+            self.stats.evictions_to_ghost += 1;
+        } else {
+            if let Some(log) = self.eviction_log.as_mut() {
+                log.record(key);
+            }
+            self.stats.full_evictions += 1;
+        }
+    }
+
+    #[inline(always)]
+    fn evict_m(&mut self) 
+    {
+        let mut evicted = false;
+        while !evicted && !self.m_queue.is_empty() {
+            let entry = if self.strategy == EvictionStrategy::MRU {
+                self.m_queue.evict_mru()
+            } else {
+                self.m_queue.evict()
+            };
+            if let Some((key, obj)) = entry {
+                let keep = !self.policy.should_evict(obj.get_freq());
+                if keep && self.strategy == EvictionStrategy::S3FIFO {
+                    let mut meta = obj.get_meta_copy(); 
+                    meta.desc_freq();
+                    
+                    self.m_queue.insert_with_meta(
+                        key, 
+                        obj.get_value_copy(),
+                        meta 
+                    );
+                    // self.m_queue.insert(key, obj.get_value_copy());
+                } else {
+                    if let Some(log) = self.eviction_log.as_mut() {
+                        log.record(key);
+                    }
+                    self.stats.full_evictions += 1;
+                    self.stats.main_evictions += 1;
+                    self.size -= 1;
+                    evicted = true;
+                }
+            }
+        }
+    }
+}
+
+///
+/// A key ranked by frequency, then by queue position (earlier is better).
+struct Ranked<'a, K> {
+    freq: usize,
+    position: usize,
+    key: &'a K,
+}
+
+impl<K> Ord for Ranked<'_, K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.freq.cmp(&other.freq)
+            .then_with(|| other.position.cmp(&self.position))
+    }
+}
+
+impl<K> PartialOrd for Ranked<'_, K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> PartialEq for Ranked<'_, K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K> Eq for Ranked<'_, K> {}
+
+///
+/// Two caches are equal if they are configured alike and hold the same
+/// entries, in the same queues and order, with the same frequencies.
+/// Only membership and order matter for the ghost queue.
+impl<K, V> PartialEq for S3FIFO<K, V>
+where 
+    K: Clone + Eq + Hash,
+    V: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.size == other.size
+            && self.cache_size == other.cache_size
+            && self.small_cache_capacity_ratio == other.small_cache_capacity_ratio
+            && self.ghost_enabled == other.ghost_enabled
+            && self.freq_promote_threshold == other.freq_promote_threshold
+            && self.strategy == other.strategy
+            && self.s_queue.eq_with_freq(&other.s_queue)
+            && self.m_queue.eq_with_freq(&other.m_queue)
+            && self.g_queue == other.g_queue
+    }
+}
+
+impl<K, V> Eq for S3FIFO<K, V>
+where 
+    K: Clone + Eq + Hash,
+    V: Eq,
+{}
+
+///
+/// `cache[&key]` reads a value without counting as an access: frequencies
+/// and stats are left alone. Panics if `key` isn't cached. There's no
+/// `IndexMut`; write through `entry` or `update_if_present` instead.
+impl<K, V> Index<&K> for S3FIFO<K, V>
+where 
+    K: Eq + Hash,
+{
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.peek(key).expect("key not found in S3FIFO cache")
+    }
+}
+
+///
+/// Read-only access to an `S3FIFO`, see `S3FIFO::as_read_only`. Lookups
+/// don't count as accesses, so handing one out can't skew eviction.
+///
+/// ```
+/// use std::num::NonZeroUsize;
+/// use s3fifo::prelude::*;
+///
+/// let mut cache: S3FIFO<u32, u32> = S3FIFO::new(NonZeroUsize::new(10).unwrap(), 0.1);
+/// cache.put(1, 10);
+/// let view = cache.as_read_only();
+/// assert_eq!(view.peek(&1), Some(&10));
+/// ```
+///
+/// None of the mutating methods are available:
+///
+/// ```compile_fail
+/// # use std::num::NonZeroUsize;
+/// # use s3fifo::prelude::*;
+/// # let mut cache: S3FIFO<u32, u32> = S3FIFO::new(NonZeroUsize::new(10).unwrap(), 0.1);
+/// cache.as_read_only().put(1, 10);
+/// ```
+///
+/// ```compile_fail
+/// # use std::num::NonZeroUsize;
+/// # use s3fifo::prelude::*;
+/// # let mut cache: S3FIFO<u32, u32> = S3FIFO::new(NonZeroUsize::new(10).unwrap(), 0.1);
+/// cache.as_read_only().get(&1);
+/// ```
+///
+/// ```compile_fail
+/// # use std::num::NonZeroUsize;
+/// # use s3fifo::prelude::*;
+/// # let mut cache: S3FIFO<u32, u32> = S3FIFO::new(NonZeroUsize::new(10).unwrap(), 0.1);
+/// cache.as_read_only().take(&1);
+/// ```
+///
+/// ```compile_fail
+/// # use std::num::NonZeroUsize;
+/// # use s3fifo::prelude::*;
+/// # let mut cache: S3FIFO<u32, u32> = S3FIFO::new(NonZeroUsize::new(10).unwrap(), 0.1);
+/// cache.as_read_only().evict_n(1);
+/// ```
+///
+/// ```compile_fail
+/// # use std::num::NonZeroUsize;
+/// # use s3fifo::prelude::*;
+/// # let mut cache: S3FIFO<u32, u32> = S3FIFO::new(NonZeroUsize::new(10).unwrap(), 0.1);
+/// cache.as_read_only().put_with_freq(1, 10, 2);
+/// ```
+#[derive(Clone, Copy)]
+pub struct ReadOnlyS3FIFO<'a, K, V>(&'a S3FIFO<K, V>);
+
+impl<'a, K, V> ReadOnlyS3FIFO<'a, K, V>
+where 
+    K: Eq + Hash,
+{
+    /// Look up `key` without touching frequencies or stats.
+    #[inline(always)]
+    pub fn peek(&self, key: &K) -> Option<&'a V> {
+        self.0.peek(key)
+    }
+
+    #[inline(always)]
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.0.peek(key).is_some()
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Same as `S3FIFO::iter_ghost_keys`.
+    pub fn iter_ghost_keys(&self) -> impl Iterator<Item = &'a K> {
+        self.0.iter_ghost_keys()
+    }
+
+    /// Cached keys, small queue first. Unlike `S3FIFO::all_keys`, ghost
+    /// keys are left out.
+    pub fn keys(&self) -> impl Iterator<Item = &'a K> {
+        self.0.s_queue.keys().chain(self.0.m_queue.keys())
+    }
+
+    /// Cached values, in the same order as `keys`.
+    pub fn values(&self) -> impl Iterator<Item = &'a V> {
+        self.0.all_values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::num::NonZeroUsize;
+    use super::{
+        BatchResult, CacheDiff, CacheOp, CacheQueryResult, EvictionStrategy, ReadOnlyS3FIFO, S3FIFO,
+        SegmentInfo,
+    };
+    use crate::error::{CacheMiss, ConfigError, ConversionError};
+    use crate::policy::{CachePolicy, DefaultS3FIFOPolicy, StrictPolicy};
+    use crate::test_utils::XorShift;
+
+    #[test]
+    fn init() {
+        let cache: S3FIFO<isize, isize> = S3FIFO::new(NonZeroUsize::new(100).unwrap(), 0.1);
+
+        assert_eq!(cache.cache_size, 100);
+        assert_eq!(cache.small_cache_capacity_ratio, 0.1);
+        assert_eq!(cache.small_cache_capacity, 10);
+        assert_eq!(cache.size, 0);
+    }
+
+    #[test]
+    fn explicit_sizes() {
+        let cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(3, 5, 4);
+        assert_eq!(cache.capacity(), 8);
+        assert_eq!(cache.small_capacity(), 3);
+        assert_eq!(cache.main_capacity(), 5);
+        assert_eq!(cache.ghost_capacity(), 4);
+        assert_eq!(cache.small_cache_capacity_ratio, 3.0 / 8.0);
+        assert!(cache.ghost_enabled());
+
+        let cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(1, 1, 0);
+        assert_eq!(cache.ghost_capacity(), 0);
+        assert!(!cache.ghost_enabled());
+    }
+
+    #[test]
+    #[should_panic]
+    fn explicit_sizes_reject_empty_main() {
+        let _: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(4, 0, 4);
+    }
+
+    #[test]
+    fn queue_key_at_follows_fifo_order() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(3, 4, 4);
+        for i in 0..3 {
+            cache.put(i, i);
+        }
+        cache.get(&0);
+        cache.get(&0);
+        assert_eq!(cache.small_queue_key_at(0), Some(&0));
+        assert_eq!(cache.small_queue_key_at(2), Some(&2));
+        assert_eq!(cache.small_queue_key_at(3), None);
+
+        // 0 is promoted on the way out, 1 goes to the ghost queue.
+        cache.put(3, 3);
+        cache.put(4, 4);
+        assert_eq!(cache.main_queue_key_at(0), Some(&0));
+        assert_eq!(cache.main_queue_key_at(1), None);
+        assert_eq!(cache.small_queue_key_at(0), Some(&2));
+        assert_eq!(cache.small_queue_key_at(1), Some(&3));
+        assert_eq!(cache.small_queue_key_at(2), Some(&4));
+    }
+
+    #[test]
+    fn ghost_hit_rate_and_load_factor() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        assert_eq!(cache.load_factor(), 0.0);
+        for _ in 0..3 {
+            for i in 0..4 {
+                if cache.get(&i).is_none() {
+                    cache.put(i, i);
+                }
+            }
+        }
+        assert!(cache.stats().ghost_hits > 0);
+        assert!(cache.stats().ghost_hit_rate() > 0.0);
+        assert_eq!(cache.load_factor(), cache.len() as f64 / 10.0);
+    }
+
+    #[test]
+    fn index_does_not_bump_freq() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        cache.put(0, 100);
+        assert_eq!(cache[&0], 100);
+        assert_eq!(cache.frequency_histogram()[0], 1);
+        assert_eq!(cache.stats().lookups(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "key not found in S3FIFO cache")]
+    fn index_missing_key_panics() {
+        let cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        let _ = cache[&0];
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        for i in 0..10 {
+            cache.put(i, i * 10);
+        }
+        for i in 0..3 {
+            cache.get(&i);
+            cache.get(&i);
+        }
+        // Push the hot keys out of the small queue into the main queue.
+        for i in 10..15 {
+            cache.put(i, i * 10);
+        }
+
+        let snapshot = cache.snapshot();
+        assert_eq!(snapshot.len(), cache.len());
+        let keys: Vec<_> = snapshot.iter().map(|(key, _, _)| *key).collect();
+        let expected: Vec<_> = cache.s_queue.keys().chain(cache.m_queue.keys()).copied().collect();
+        assert_eq!(keys, expected);
+        assert_eq!(snapshot.last(), Some(&(2, 20, 0)));
+
+        let restored = S3FIFO::restore_from_snapshot(100, 0.1, snapshot.clone());
+        let mut before = snapshot;
+        let mut after = restored.snapshot();
+        before.sort();
+        after.sort();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn evict_s_with_only_hot_entries_terminates() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(5, 0.4).unwrap();
+        cache.put(0, 0);
+        cache.put(1, 1);
+        for _ in 0..2 {
+            cache.get(&0);
+            cache.get(&1);
+        }
+        cache.warm_from_iter((2..5).map(|i| (i, i, 2)));
+        assert_eq!(cache.s_queue.len(), 2);
+        assert!(cache.m_queue.is_full());
+
+        // Both small entries get promoted, so room has to come from main.
+        assert_eq!(cache.put(5, 5), None);
+        assert!(cache.s_queue.peek(&5).is_some());
+        assert!(cache.len() <= cache.capacity());
+        assert_eq!(cache.stats().promotions_to_main, 2);
+    }
+
+    #[test]
+    fn evict_n_counts_main_queue_overflow() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(5, 0.4).unwrap();
+        cache.put(0, 0);
+        cache.put(1, 1);
+        cache.get(&0);
+        cache.get(&0);
+        cache.warm_from_iter((2..5).map(|i| (i, i, 2)));
+        assert!(cache.m_queue.is_full());
+
+        // Promoting 0 evicts 2 from main, then 1 goes to the ghost queue.
+        assert_eq!(cache.evict_n(1), 2);
+        assert_eq!(cache.len(), 3);
+        assert!(cache.peek(&0).is_some());
+        assert!(cache.peek(&1).is_none());
+        assert!(cache.peek(&2).is_none());
+
+        assert_eq!(cache.evict_n(10), 3);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn capacity_utilization_report() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        for i in 0..3 {
+            cache.put(i, i);
+        }
+        cache.get(&2);
+        cache.get(&5);
+
+        let report = cache.capacity_utilization_report();
+        assert!(!report.is_empty());
+        for segment in ["small", "main", "ghost"] {
+            assert!(report.contains(segment));
+        }
+        assert!(report.contains("100.0%"));
+        assert!(report.contains("20.0%"));
+        assert!(report.contains("0:1 1:1 2:0 3:0"));
+        assert!(report.contains("50.0%"));
+    }
+
+    #[test]
+    fn matching() {
+        let snapshot = (1..=100).map(|i| (i, i, 0)).collect();
+        let cache: S3FIFO<isize, isize> = S3FIFO::restore_from_snapshot(100, 0.1, snapshot);
+        assert_eq!(cache.len(), 100);
+        assert_eq!(cache.count_matching(|_, v| *v > 50), 50);
+
+        let mut small = cache.get_all_matching(|k, _| *k <= 5);
+        small.sort();
+        assert_eq!(small, vec![(&1, &1), (&2, &2), (&3, &3), (&4, &4), (&5, &5)]);
+        assert_eq!(cache.frequency_histogram()[0], 100);
+    }
+
+    #[test]
+    fn hash_map_round_trip() {
+        let map: HashMap<isize, isize> = (0..20).map(|i| (i, i * 10)).collect();
+        let cache = S3FIFO::try_from(map.clone()).unwrap();
+        assert_eq!(cache.capacity(), 20);
+        assert_eq!(cache.small_capacity(), 2);
+        assert_eq!(cache.len(), 20);
+
+        let back: HashMap<isize, isize> = cache.into();
+        assert_eq!(back, map);
+
+        let single: HashMap<isize, isize> = HashMap::from([(1, 1)]);
+        let cache = S3FIFO::try_from(single.clone()).unwrap();
+        assert_eq!(HashMap::from(cache), single);
+    }
+
+    #[test]
+    fn try_from_empty_map() {
+        let map: HashMap<isize, isize> = HashMap::new();
+        assert_eq!(S3FIFO::try_from(map).err(), Some(ConversionError::EmptyMap));
+    }
+
+    #[test]
+    fn mem_usage_bytes() {
+        let mut cache: S3FIFO<u64, u64> = S3FIFO::try_new(100, 0.1).unwrap();
+        let empty = cache.mem_usage_bytes();
+        for i in 0..10 {
+            cache.put(i, i);
+        }
+
+        let usage = cache.mem_usage_bytes();
+        assert!(usage >= 10 * (8 + 8));
+        assert!(usage - empty <= 10 * 64);
+        // Ring buffers: 100 cache slots plus 90 ghost slots.
+        assert_eq!(empty, 190 * 8);
+    }
+
+    #[test]
+    fn pure_fifo_strategy() {
+        let mut cache: S3FIFO<isize, isize> = 
+            S3FIFO::with_strategy(4, 0.25, EvictionStrategy::PureFIFO);
+        assert_eq!(cache.strategy(), EvictionStrategy::PureFIFO);
+        assert_eq!(cache.main_capacity(), 4);
+        assert!(!cache.ghost_enabled());
+
+        for i in 0..4 {
+            cache.put(i, i);
+        }
+        cache.get(&0);
+        cache.get(&0);
+        cache.put(4, 4);
+        // Reads don't save 0 from being evicted first.
+        assert_eq!(cache.get(&0), None);
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.small_occupancy(), 0.0);
+
+        cache.put(0, 0);
+        assert_eq!(cache.get(&0), Some(&0));
+        assert_eq!(cache.get(&1), None);
+    }
+
+    #[test]
+    fn single_queue_strategies_leave_small_and_ghost_unallocated() {
+        for strategy in [EvictionStrategy::PureFIFO, EvictionStrategy::LRU] {
+            let mut cache: S3FIFO<isize, isize> = S3FIFO::with_strategy(8, 0.25, strategy);
+            for i in 0..20 {
+                cache.put(i, i);
+                cache.get(&(i / 2));
+            }
+            cache.resize(16);
+            cache.put(100, 100);
+            assert_eq!(cache.s_queue.allocated_slots(), 0);
+            assert_eq!(cache.g_queue.allocated_slots(), 0);
+            cache.compact();
+            cache.assert_invariants();
+        }
+
+        let cache: S3FIFO<isize, isize> = S3FIFO::with_ghost_disabled(8, 0.25);
+        assert_eq!(cache.g_queue.allocated_slots(), 0);
+    }
+
+    #[test]
+    fn lru_strategy() {
+        let mut cache: S3FIFO<isize, isize> = 
+            S3FIFO::with_strategy(3, 0.3, EvictionStrategy::LRU);
+        for i in 0..3 {
+            cache.put(i, i);
+        }
+        cache.get(&0);
+        cache.put(3, 3);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&0), Some(&0));
+
+        cache.get(&2);
+        cache.put(4, 4);
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.all_keys().collect::<Vec<_>>(), vec![&0, &2, &4]);
+    }
+
+    #[test]
+    fn atomic_get_put_computes_once() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        let mut calls = 0;
+        for _ in 0..5 {
+            let value = cache.atomic_get_put(7, |key| {
+                calls += 1;
+                key * 2
+            });
+            assert_eq!(value, &14);
+            assert_eq!(cache.get(&7), Some(&14));
+        }
+        assert_eq!(calls, 1);
+        assert_eq!(cache.stats().misses, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "size is 1 but the small and main queues hold 0 and 0 entries")]
+    fn assert_invariants_catches_size_drift() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        cache.size = 1;
+        cache.assert_invariants();
+    }
+
+    #[test]
+    fn get_or_ghost() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        cache.put(0, 0);
+        cache.put(1, 1);
+        cache.get(&0);
+        cache.get(&0);
+        cache.put(2, 2);
+
+        assert_eq!(cache.get_or_ghost(&0), CacheQueryResult::MainHit(&0));
+        assert_eq!(cache.get_or_ghost(&2), CacheQueryResult::SmallHit(&2));
+        assert_eq!(cache.get_or_ghost(&5), CacheQueryResult::Miss);
+
+        let len = cache.len();
+        let histogram = cache.frequency_histogram();
+        let result = cache.get_or_ghost(&1);
+        assert_eq!(result, CacheQueryResult::GhostHit);
+        assert!(!result.is_hit());
+        assert_eq!(result.value(), None);
+        assert_eq!(cache.len(), len);
+        assert_eq!(cache.frequency_histogram(), histogram);
+
+        assert!(cache.get_or_ghost(&2).is_hit());
+        assert_eq!(cache.get_or_ghost(&2).value(), Some(&&2));
+        assert_eq!(cache.stats().misses, 2);
+    }
+
+    #[test]
+    fn into_queue_entries() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(3, 8, 8);
+        for i in 0..3 {
+            cache.put(i, i * 10);
+        }
+        for hot in [0, 2] {
+            cache.get(&hot);
+            cache.get(&hot);
+        }
+        // Evicting the small queue promotes the hot keys.
+        for i in 3..6 {
+            cache.put(i, i * 10);
+        }
+
+        assert_eq!(cache.main_entries_cloned(), vec![(0, 0), (2, 20)]);
+        assert_eq!(cache.small_entries_cloned(), vec![(4, 40), (5, 50)]);
+        assert_eq!(cache.clone().into_small_entries(), vec![(4, 40), (5, 50)]);
+        assert_eq!(cache.into_main_entries(), vec![(0, 0), (2, 20)]);
+    }
+
+    #[test]
+    fn mru_strategy() {
+        let mut cache: S3FIFO<isize, isize> = 
+            S3FIFO::with_strategy(3, 0.3, EvictionStrategy::MRU);
+        for i in 0..3 {
+            cache.put(i, i);
+        }
+        cache.get(&2);
+        cache.put(3, 3);
+        assert_eq!(cache.get(&2), None);
+        cache.put(4, 4);
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.all_keys().collect::<Vec<_>>(), vec![&0, &1, &4]);
+    }
+
+    #[test]
+    fn get_copy_or_default_and_get_or_else() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        assert_eq!(cache.get_copy_or_default(&1), 0);
+        assert!(cache.is_empty());
+
+        let mut calls = 0;
+        for _ in 0..3 {
+            let value = cache.get_or_else(&1, || {
+                calls += 1;
+                7
+            });
+            assert_eq!(value, &7);
+        }
+        assert_eq!(calls, 1);
+        assert_eq!(cache.get_copy_or_default(&1), 7);
+        assert_eq!(cache.stats().hits, 3);
+    }
+
+    #[test]
+    fn resize() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(20, 0.2).unwrap();
+        for i in 0..4 {
+            cache.put(i, i);
+        }
+        cache.get(&0);
+        cache.get(&0);
+        cache.put(4, 4);
+        cache.put(5, 5);
+        assert!(cache.m_queue.peek(&0).is_some());
+
+        cache.resize(40);
+        assert_eq!(cache.capacity(), 40);
+        assert_eq!(cache.small_capacity(), 8);
+        assert_eq!(cache.main_capacity(), 32);
+        assert_eq!(cache.ghost_capacity(), 32);
+        assert!(cache.m_queue.peek(&0).is_some());
+        assert_eq!(cache.all_keys().collect::<Vec<_>>(), vec![&2, &3, &4, &5, &0, &1]);
+
+        // Shrinking the small queue evicts its oldest entries.
+        cache.resize(10);
+        assert_eq!(cache.small_capacity(), 2);
+        assert_eq!(cache.s_queue.keys().collect::<Vec<_>>(), vec![&4, &5]);
+        assert!(cache.m_queue.peek(&0).is_some());
+        assert_eq!(cache.len(), 3);
+        cache.assert_invariants();
+    }
+
+    #[test]
+    fn resize_ratio() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(20, 0.5).unwrap();
+        for i in 0..8 {
+            cache.put(i, i);
+            if i < 4 {
+                cache.get(&i);
+                cache.get(&i);
+            }
+        }
+        assert_eq!(cache.s_queue.len(), 8);
+
+        // The small queue shrinks to 4: the hot half moves to main.
+        cache.resize_ratio(0.2);
+        assert_eq!((cache.small_capacity(), cache.main_capacity()), (4, 16));
+        assert_eq!(cache.capacity(), 20);
+        assert_eq!(cache.len(), 8);
+        assert_eq!(cache.m_queue.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(cache.s_queue.keys().copied().collect::<Vec<_>>(), vec![4, 5, 6, 7]);
+
+        // Growing the small queue loses nothing.
+        cache.resize_ratio(0.8);
+        assert_eq!((cache.small_capacity(), cache.main_capacity()), (16, 4));
+        assert_eq!(cache.len(), 8);
+
+        // The main queue shrinks to 2 and has to evict.
+        cache.resize_ratio(0.9);
+        assert_eq!(cache.m_queue.keys().copied().collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(cache.len(), 6);
+        cache.assert_invariants();
+    }
+
+    #[test]
+    fn merge() {
+        let mut a: S3FIFO<isize, isize> = S3FIFO::try_new(50, 0.1).unwrap();
+        let mut b: S3FIFO<isize, isize> = S3FIFO::try_new(50, 0.3).unwrap();
+        a.warm_from_iter((0..45).map(|i| (i, i, 2)));
+        b.warm_from_iter((50..85).map(|i| (i, i, 2)));
+        for i in 45..50 {
+            a.put(i, i);
+        }
+        for i in 85..100 {
+            b.put(i, i);
+        }
+        assert_eq!(a.len() + b.len(), 100);
+
+        let merged = S3FIFO::merge(a, b);
+        assert_eq!(merged.capacity(), 100);
+        assert_eq!(merged.small_capacity(), 20);
+        assert_eq!(merged.len(), 100);
+        assert!((0..100).all(|key| merged.peek(&key) == Some(&key)));
+        assert_eq!(merged.m_queue.peek(&0).unwrap().get_freq(), 2);
+        assert!(merged.s_queue.peek(&49).is_some());
+        merged.assert_invariants();
+    }
+
+    #[test]
+    fn merge_conflicts_and_ghosts() {
+        let mut a: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 4, 4);
+        let mut b: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(4, 4, 4);
+        a.put_with_freq(1, 10, 1);
+        a.put_with_freq(2, 20, 2);
+        for i in 3..8 {
+            b.put(i, i);
+        }
+        b.put_with_freq(1, 11, 2);
+        b.put_with_freq(2, 21, 2);
+        let ghosts: Vec<isize> = b.g_queue.keys().copied().collect();
+        assert!(!ghosts.is_empty());
+
+        let merged = S3FIFO::merge(a, b);
+        assert_eq!(merged.peek(&1), Some(&11));
+        assert_eq!(merged.peek(&2), Some(&20));
+        assert!(ghosts.iter().all(|key| merged.is_in_ghost(key)));
+        merged.assert_invariants();
+    }
+
+    #[test]
+    fn adaptive_ratio_update() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        for i in 0..10 {
+            cache.adaptive_ratio_update(0.2 - i as f64 * 0.01);
+        }
+        assert!(cache.small_cache_capacity_ratio > 0.1);
+        assert!(cache.small_capacity() > 10);
+
+        // A steady hit rate with nothing to promote leaves the ratio alone.
+        let ratio = cache.small_cache_capacity_ratio;
+        cache.adaptive_ratio_update(0.9);
+        assert_eq!(cache.small_cache_capacity_ratio, ratio);
+
+        // Mostly hot small queue entries shrink it.
+        for i in 0..4 {
+            cache.put(i, i);
+            cache.get(&i);
+            cache.get(&i);
+        }
+        cache.adaptive_ratio_update(0.9);
+        assert!(cache.small_cache_capacity_ratio < ratio);
+    }
+
+    #[cfg(feature = "admission_sketch")]
+    #[test]
+    fn sketch_rejects_ghost_hit_colder_than_victim() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(1, 3, 3);
+        cache.warm_from_iter([(10, 10, 2), (11, 11, 2)]);
+        for _ in 0..5 {
+            cache.get(&10);
+        }
+        cache.put(0, 0);
+        cache.put(1, 1);
+        cache.warm_from_iter([(12, 12, 2)]);
+        assert!(cache.g_queue.peek(&0).is_some());
+        assert!(cache.m_queue.is_full());
+
+        // 0 was seen twice, 10 (main's next victim) five times.
+        cache.put(0, 0);
+        assert!(cache.s_queue.peek(&0).is_some());
+        assert_eq!(cache.stats().ghost_hits, 1);
+    }
+
+    #[cfg(feature = "admission_sketch")]
+    #[test]
+    fn get_or_ghost_feeds_the_sketch() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(1, 3, 3);
+        for _ in 0..4 {
+            cache.get_or_ghost(&7);
+        }
+        assert!(cache.sketch.estimate(&7) >= 4);
+    }
+
+    #[test]
+    fn partition_by() {
+        let snapshot = (0..100).map(|i| (i, i, 0)).collect();
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::restore_from_snapshot(100, 0.1, snapshot);
+
+        let evens = cache.partition_by(|k, _| k % 2 == 0);
+        assert_eq!(evens.len(), 50);
+        assert!(evens.iter().all(|(k, v)| k % 2 == 0 && k == v));
+        assert_eq!(cache.len(), 50);
+        assert_eq!(cache.count_matching(|k, _| k % 2 == 1), 50);
+        assert_eq!(cache.s_queue.keys().copied().collect::<Vec<_>>(), vec![1, 3, 5, 7, 9]);
+        assert!(cache.g_queue.is_empty());
+    }
+
+    #[test]
+    fn map_values() {
+        let mut cache: S3FIFO<i32, i32> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        for i in 0..4 {
+            cache.put(i, i * 10);
+            cache.get(&i);
+            cache.get(&i);
+        }
+
+        let mapped: S3FIFO<i32, String> = cache.map_values(|v| v.to_string());
+        assert_eq!(mapped.len(), cache.len());
+        assert_eq!(mapped.segment_info(), cache.segment_info());
+        assert_eq!(mapped.snapshot(), cache.snapshot().into_iter()
+            .map(|(k, v, freq)| (k, v.to_string(), freq))
+            .collect::<Vec<_>>());
+        assert_eq!(mapped[&3], "30");
+        mapped.assert_invariants();
+    }
+
+    #[test]
+    fn map_keys() {
+        let mut cache: S3FIFO<String, i32> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        for (i, key) in ["a", "bb", "cc", "ddd", "eeee"].into_iter().enumerate() {
+            cache.put(key.to_string(), i as i32);
+            cache.get(&key.to_string());
+            cache.get(&key.to_string());
+        }
+        let source = cache.snapshot();
+
+        let mapped: S3FIFO<usize, i32> = cache.map_keys(|s: String| s.len());
+        mapped.assert_invariants();
+        assert_eq!(mapped.segment_info().small_capacity, 2);
+        // "bb" and "cc" collide: the later one wins.
+        assert_eq!(mapped.len(), cache.len() - 1);
+        assert_eq!(mapped[&2], 2);
+        for (key, value, freq) in source {
+            if key.len() != 2 {
+                assert!(mapped.snapshot().contains(&(key.len(), value, freq)));
+            }
+        }
+    }
+
+    #[test]
+    fn prefetch() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        for i in 0..10 {
+            cache.prefetch(i, i);
+        }
+        cache.prefetch(9, 90);
+        assert_eq!(cache.stats().prefetch_count, 10);
+        assert_eq!(cache.stats().lookups(), 0);
+        assert_eq!(cache[&9], 9);
+
+        for i in 10..20 {
+            cache.put(i, i);
+            cache.get(&i);
+            cache.get(&i);
+        }
+        assert!((0..10).all(|i| cache.peek(&i).is_none()));
+        assert!(cache.g_queue.is_empty());
+        assert_eq!(cache.count_matching(|k, _| *k >= 10), cache.len());
+    }
+
+    #[test]
+    fn get_or_err() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        cache.put(1, 10);
+        assert_eq!(cache.get_or_err(&1), Ok(&10));
+
+        let err = cache.get_or_err(&-7).unwrap_err();
+        assert_eq!(err, CacheMiss { key: &-7 });
+        assert!(err.to_string().contains("-7"));
+
+        *cache.try_get_mut(&1).unwrap() += 1;
+        assert_eq!(cache[&1], 11);
+        assert_eq!(cache.try_get_mut(&2).unwrap_err().key, &2);
+        assert_eq!(cache.stats().lookups(), 2);
+    }
+
+    #[test]
+    fn with_capacity_check() {
+        let check = |size, ratio| S3FIFO::<usize, usize>::with_capacity_check(size, ratio).err();
+        assert_eq!(check(10, 0.0), Some(ConfigError::InvalidRatio));
+        assert_eq!(check(10, 1.0), Some(ConfigError::InvalidRatio));
+        assert_eq!(check(10, f64::NAN), Some(ConfigError::InvalidRatio));
+        assert_eq!(check(0, 0.1), Some(ConfigError::ZeroCapacity));
+        assert_eq!(check(5, 0.1), Some(ConfigError::SmallQueueZeroCapacity));
+        assert_eq!(check(10, 0.2), None);
+
+        let cache: S3FIFO<usize, usize> = S3FIFO::with_capacity_check(10, 0.2).ok().unwrap();
+        assert_eq!(cache.capacity(), 10);
+        assert_eq!(cache.segment_info().small_capacity, 2);
+    }
+
+    #[test]
+    fn try_new() {
+        assert!(S3FIFO::<isize, isize>::try_new(0, 0.1).is_none());
+        assert!(S3FIFO::<isize, isize>::try_new(10, 1.5).is_none());
+        assert_eq!(S3FIFO::<isize, isize>::try_new(10, 0.2).unwrap().capacity(), 10);
+    }
+
+    #[test]
+    fn config_error_display() {
+        assert!(ConfigError::MainQueueZeroCapacity.to_string().contains("main queue"));
+    }
+
+    #[test]
+    fn apply_batch() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        let results = cache.apply_batch([
+            CacheOp::Put(1, 10),
+            CacheOp::Get(1),
+            CacheOp::Get(2),
+            CacheOp::Put(1, 11),
+            CacheOp::Touch(1),
+            CacheOp::Touch(2),
+            CacheOp::Remove(1),
+            CacheOp::Remove(1),
+        ]);
+
+        assert_eq!(results, vec![
+            BatchResult::Inserted(None),
+            BatchResult::Hit(10),
+            BatchResult::Miss,
+            BatchResult::Inserted(Some(10)),
+            BatchResult::Touched(true),
+            BatchResult::Touched(false),
+            BatchResult::Removed(11),
+            BatchResult::Miss,
+        ]);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn save_and_load_file() {
+        let mut cache: S3FIFO<isize, String> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        for i in 0..4 {
+            cache.put(i, format!("v{}", i));
+            cache.get(&i);
+            cache.get(&i);
+        }
+        // Promotes the hot keys and evicts 4 and 5 into the ghost queue.
+        for i in 4..8 {
+            cache.put(i, format!("v{}", i));
+        }
+        cache.get(&7);
+        assert!(!cache.m_queue.is_empty() && !cache.s_queue.is_empty() && !cache.g_queue.is_empty());
+
+        let path = std::env::temp_dir()
+            .join(format!("s3fifo-save-and-load-{}.bin", std::process::id()));
+        cache.save_to_file(&path).unwrap();
+        let expected = (
+            cache.snapshot(),
+            cache.s_queue.keys().copied().collect::<Vec<_>>(),
+            cache.g_queue.keys().copied().collect::<Vec<_>>(),
+        );
+        drop(cache);
+
+        let loaded: S3FIFO<isize, String> = S3FIFO::load_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.segment_info().small_capacity, 2);
+        assert_eq!(
+            (
+                loaded.snapshot(),
+                loaded.s_queue.keys().copied().collect::<Vec<_>>(),
+                loaded.g_queue.keys().copied().collect::<Vec<_>>(),
+            ),
+            expected
+        );
+        loaded.assert_invariants();
+    }
+
+    #[test]
+    fn save_and_load_keeps_config() {
+        let path = |name: &str| std::env::temp_dir()
+            .join(format!("s3fifo-keeps-config-{}-{}.bin", name, std::process::id()));
+        let round_trip = |cache: &S3FIFO<isize, isize>, name: &str| {
+            let path = path(name);
+            cache.save_to_file(&path).unwrap();
+            let loaded: S3FIFO<isize, isize> = S3FIFO::load_from_file(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            loaded
+        };
+
+        let explicit: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(3, 7, 2);
+        let loaded = round_trip(&explicit, "explicit");
+        assert_eq!(loaded.segment_info(), explicit.segment_info());
+        assert!(loaded.ghost_enabled());
+        assert!(loaded == explicit);
+
+        let no_ghost: S3FIFO<isize, isize> = S3FIFO::with_ghost_disabled(10, 0.2);
+        let loaded = round_trip(&no_ghost, "no-ghost");
+        assert_eq!(loaded.segment_info(), no_ghost.segment_info());
+        assert!(!loaded.ghost_enabled());
+
+        let strict: S3FIFO<isize, isize> = S3FIFO::new_with_config(10, 0.2, 3);
+        assert_eq!(round_trip(&strict, "threshold").freq_promote_threshold(), 3);
+
+        // Re-warming a ghost key leaves it in the ghost ring twice.
+        let mut dup: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(1, 1, 4);
+        dup.put(0, 0);
+        dup.put(1, 1);
+        dup.warm_from_iter([(0, 0, 0)]);
+        dup.put(2, 2);
+        let loaded = round_trip(&dup, "dup-ghost");
+        let mut ghosts: Vec<isize> = loaded.iter_ghost_keys().copied().collect();
+        ghosts.sort();
+        ghosts.dedup();
+        assert_eq!(ghosts.len(), loaded.iter_ghost_keys().count());
+        loaded.assert_invariants();
+    }
+
+    #[test]
+    fn load_rejects_garbage() {
+        let err = S3FIFO::<isize, isize>::read_from(&mut &b"nope, not a cache"[..]).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        let mut bytes = Vec::new();
+        let cache: S3FIFO<isize, isize> = S3FIFO::try_new(10, 0.2).unwrap();
+        cache.write_to(&mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(S3FIFO::<isize, isize>::read_from(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn diff() {
+        let mut a: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        for i in 0..5 {
+            a.put(i, i);
+        }
+        let mut b = a.clone();
+        // Frequencies don't matter.
+        b.get(&0);
+        assert!(a.diff(&b).is_empty());
+
+        a.put(5, 5);
+        b.put(1, 10);
+        b.put(6, 6);
+        // Ghost keys aren't cached.
+        b.g_queue.insert(7, ());
+
+        assert_eq!(a.diff(&b), CacheDiff {
+            only_in_self: vec![5],
+            only_in_other: vec![6],
+            changed: vec![(1, 1, 10)],
+        });
+        assert!(!b.diff(&a).is_empty());
+    }
+
+    #[test]
+    fn insert_batch_with_priority() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(5, 5, 5);
+        let high: Vec<_> = (0..5).map(|i| (i, i)).collect();
+        cache.insert_batch_with_priority(high, Vec::new());
+        assert_eq!(cache.m_queue.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert!(cache.s_queue.is_empty());
+
+        let low: Vec<_> = (5..10).map(|i| (i, i)).collect();
+        cache.insert_batch_with_priority(Vec::new(), low);
+        assert_eq!(cache.s_queue.keys().copied().collect::<Vec<_>>(), vec![5, 6, 7, 8, 9]);
+        assert_eq!(cache.m_queue.keys().copied().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(cache.len(), 10);
+
+        // Cached keys are updated in place.
+        cache.insert_batch_with_priority(vec![(5, 50)], vec![(0, 0)]);
+        assert_eq!(cache[&5], 50);
+        assert_eq!(cache.len(), 10);
+        assert!(cache.g_queue.is_empty());
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(10000, 0.1).unwrap();
+        for i in 0..1000 {
+            cache.put(i, i);
+        }
+        for i in 100..1000 {
+            cache.take(&i);
+        }
+        let overhead = cache.memory_overhead_estimate();
+
+        let usage = cache.mem_usage_bytes();
+        cache.shrink_to_fit();
+        assert_eq!(cache.len(), 100);
+        assert_eq!(cache.capacity(), 10000);
+        assert!(cache.memory_overhead_estimate() < overhead);
+        assert!(cache.mem_usage_bytes() < usage / 10);
+        assert_eq!(cache.m_queue.allocated_slots(), cache.m_queue.len().max(1));
+        cache.assert_invariants();
+
+        for i in 1000..1100 {
+            cache.put(i, i);
+        }
+        assert_eq!(cache.len(), 200);
+        assert_eq!(cache.get(&50), Some(&50));
+        assert_eq!(cache.get(&1050), Some(&1050));
+    }
+
+    #[test]
+    fn get_with_fallback() {
+        use std::borrow::Cow;
+
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        cache.put(1, 10);
+        let mut calls = 0;
+
+        let hit = cache.get_with_fallback(&1, || { calls += 1; -1 });
+        assert!(matches!(hit, Cow::Borrowed(&10)));
+        assert_eq!(calls, 0);
+
+        let miss = cache.get_with_fallback(&2, || { calls += 1; -1 });
+        assert!(matches!(miss, Cow::Owned(-1)));
+        assert_eq!(calls, 1);
+        assert!(cache.peek(&2).is_none());
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn age_all_frequencies() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 4, 4);
+        cache.insert_batch_with_priority(vec![(0, 0), (1, 1)], vec![(10, 10)]);
+        for (key, reads) in [(0, 3), (1, 1), (10, 2)] {
+            for _ in 0..reads {
+                cache.get(&key);
+            }
+        }
+        let freqs = |cache: &S3FIFO<isize, isize>| {
+            cache.snapshot().into_iter().map(|(key, _, freq)| (key, freq)).collect::<Vec<_>>()
+        };
+        assert_eq!(freqs(&cache), vec![(10, 2), (0, 3), (1, 1)]);
+
+        cache.age_all_frequencies();
+        assert_eq!(freqs(&cache), vec![(10, 1), (0, 2), (1, 0)]);
+
+        // 10 is no longer hot enough to be promoted.
+        assert_eq!(cache.evict_n(1), 1);
+        assert!(cache.peek(&10).is_none());
+        // 1 is evicted from the main queue, 0 survives another pass.
+        assert_eq!(cache.evict_n(1), 1);
+        assert!(cache.peek(&1).is_none());
+        assert_eq!(freqs(&cache), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn get_neighbor_keys() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        for i in 0..10 {
+            cache.put(i, i);
+        }
+        assert_eq!(cache.get_neighbor_keys(&5, 2), (vec![&3, &4], vec![&6, &7]));
+        assert_eq!(cache.get_neighbor_keys(&1, 3), (vec![&0], vec![&2, &3, &4]));
+        assert_eq!(cache.get_neighbor_keys(&9, 1), (vec![&8], vec![]));
+        assert_eq!(cache.get_neighbor_keys(&42, 1), (vec![], vec![]));
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(1, 4, 4);
+        cache.insert_batch_with_priority(vec![(0, 0)], vec![(1, 1)]);
+
+        assert!(cache.compare_and_swap(&0, &0, 10));
+        assert!(cache.compare_and_swap(&1, &1, 11));
+        assert_eq!((cache[&0], cache[&1]), (10, 11));
+
+        assert!(!cache.compare_and_swap(&0, &0, 20));
+        assert_eq!(cache[&0], 10);
+        assert!(!cache.compare_and_swap(&2, &0, 20));
+        assert!(cache.peek(&2).is_none());
+        assert_eq!(cache.snapshot(), vec![(1, 11, 0), (0, 10, 0)]);
+    }
+
+    #[test]
+    fn strict_policy_promotes_less() {
+        let run = |mut cache: S3FIFO<usize, usize>| {
+            let mut rng = XorShift::new(7);
+            for _ in 0..5000 {
+                // Skewed: low keys are requested far more often.
+                let bound = 1 + rng.below(200);
+                let key = rng.below(bound);
+                if cache.get(&key).is_none() {
+                    cache.put(key, key);
+                }
+            }
+            cache.assert_invariants();
+            cache.stats().promotions_to_main
+        };
+
+        let default = run(S3FIFO::with_policy(50, 0.1, DefaultS3FIFOPolicy));
+        let strict = run(S3FIFO::with_policy(50, 0.1, StrictPolicy));
+        assert!(default > 0);
+        assert!(strict < default, "strict {} vs default {}", strict, default);
+    }
+
+    #[test]
+    fn custom_policy() {
+        struct NeverPromote;
+        impl CachePolicy for NeverPromote {
+            fn should_promote(&self, _freq: usize, _threshold: usize) -> bool { false }
+            fn should_evict(&self, _freq: usize) -> bool { true }
+        }
+
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::with_policy(10, 0.5, NeverPromote);
+        for i in 0..20 {
+            cache.put(i, i);
+            cache.get(&i);
+            cache.get(&i);
+        }
+        assert_eq!(cache.stats().promotions_to_main, 0);
+        assert!(cache.m_queue.is_empty());
+    }
+
+    #[test]
+    fn is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<S3FIFO<String, Vec<u8>>>();
+        assert_send_sync::<crate::fifo_cache::FIFOCache<String, Vec<u8>>>();
+        assert_send_sync::<crate::ring_buffer::RingBuffer<String>>();
+    }
+
+    #[test]
+    fn eviction_log() {
+        let logged = |cache: &S3FIFO<isize, isize>| {
+            cache.eviction_log().unwrap().iter().copied().collect::<Vec<_>>()
+        };
+
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 2, 2)
+            .with_eviction_log(16);
+        // Hot keys, promoted to the main queue.
+        for i in 0..2 {
+            cache.put(i, i);
+            cache.get(&i);
+            cache.get(&i);
+        }
+        for i in 2..8 {
+            cache.put(i, i);
+        }
+        // Cold keys went to the ghost queue, which isn't logged; main queue
+        // evictions are full evictions.
+        let main: Vec<_> = cache.m_queue.keys().copied().collect();
+        let cached = cache.len();
+        assert_eq!(cache.evict_n(cached), cached);
+        let evicted = logged(&cache);
+        assert!(!main.is_empty() && main.iter().all(|key| evicted.contains(key)));
+        assert!(evicted.iter().all(|key| !cache.is_in_ghost(key)));
+        assert!((2..8).all(|key| !evicted.contains(&key)));
+
+        // Without a ghost queue every eviction is logged: each key is
+        // either still cached or logged, exactly once, in eviction order.
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::with_ghost_disabled(4, 0.5)
+            .with_eviction_log(16);
+        for i in 0..8 {
+            cache.put(i, i);
+        }
+        let mut seen = logged(&cache);
+        assert!(seen.windows(2).all(|pair| pair[0] < pair[1]));
+        seen.extend(cache.snapshot().into_iter().map(|(key, _, _)| key));
+        seen.sort();
+        assert_eq!(seen, (0..8).collect::<Vec<_>>());
+
+        let plain: S3FIFO<isize, isize> = S3FIFO::try_new(10, 0.2).unwrap();
+        assert!(plain.eviction_log().is_none());
+    }
+
+    #[test]
+    fn capacities_match_queues() {
+        for (cache_size, ratio) in [(100, 0.1), (10, 0.29), (7, 0.5), (1000, 0.33)] {
+            let cache: S3FIFO<isize, isize> = S3FIFO::try_new(cache_size, ratio).unwrap();
+            assert_eq!(cache.s_queue.capacity(), cache.small_capacity());
+            assert_eq!(cache.m_queue.capacity(), cache.main_capacity());
+            assert_eq!(cache.g_queue.capacity(), cache.ghost_capacity());
+            assert_eq!(cache.small_capacity() + cache.main_capacity(), cache.capacity());
+        }
+
+        let cache: S3FIFO<isize, isize> = S3FIFO::with_ghost_disabled(100, 0.1);
+        assert_eq!(cache.ghost_capacity(), 0);
+        assert_eq!(cache.small_capacity(), 10);
+        assert_eq!(cache.main_capacity(), 90);
+    }
+
+    #[test]
+    fn default_init() {
+        let mut cache = S3FIFO::<i32, i32>::default();
+        assert_eq!(cache.capacity(), S3FIFO::<i32, i32>::DEFAULT_CAPACITY);
+        assert_eq!(cache.capacity(), 1024);
+        assert_eq!(cache.small_cache_capacity_ratio, 0.1);
+        assert!(cache.is_empty());
+
+        cache.put(0, 0);
+        assert_eq!(cache.get(&0), Some(&0));
+
+        let cache: S3FIFO<i32, i32> = S3FIFO::new_with_default_ratio(100);
+        assert_eq!(cache.small_capacity(), 10);
+    }
+
+    #[test]
+    fn empty_get() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        let result = cache.get(&1);
+        assert!(result.is_none());
+        assert_eq!(cache.size, 0);
+    }
+
+    #[test]
+    fn put_value() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        cache.put(0, 0);
+
+        assert_eq!(cache.size, 1);
+        assert_eq!(cache.s_queue.len(), 1);
+        assert_eq!(cache.m_queue.len(), 0);
+        assert_eq!(cache.g_queue.len(), 0);
+
+        cache.put(1, 1);
+        assert_eq!(cache.size, 2);
+        assert_eq!(cache.s_queue.len(), 2);
+        assert_eq!(cache.m_queue.len(), 0);
+        assert_eq!(cache.g_queue.len(), 0);
+    }
+
+    #[test]
     fn dup_put() {
-        let mut cache: S3FIFO<isize, isize> = S3FIFO::new(100, 0.1);
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        cache.put(0, 0);
+
+        assert_eq!(cache.size, 1);
+        assert_eq!(cache.s_queue.len(), 1);
+        assert_eq!(cache.m_queue.len(), 0);
+        assert_eq!(cache.g_queue.len(), 0);
+
+        cache.put(0, 1);
+        assert_eq!(cache.size, 1);
+        assert_eq!(cache.s_queue.len(), 1);
+        assert_eq!(cache.m_queue.len(), 0);
+        assert_eq!(cache.g_queue.len(), 0);
+    }
+
+    #[test]
+    fn simple_put_get() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        cache.put(0, 0);
+        let result = cache.get(&0);
+        assert_eq!(result, Some(&0));
+    }
+
+    #[test]
+    fn multiple_put_get() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        for i in 0..50 {
+            cache.put(i, i);
+            let result = cache.get(&i);
+            assert_eq!(result, Some(&i));
+        }
+    }
+
+    #[test]
+    fn eq_same_puts() {
+        let mut a: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        let mut b: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        for i in 0..50 {
+            a.put(i, i);
+            b.put(i, i);
+        }
+        assert!(a == b);
+    }
+
+    #[test]
+    fn ne_after_get() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        for i in 0..5 {
+            cache.put(i, i);
+        }
+        let pristine = cache.clone();
+        assert!(cache == pristine);
+
+        cache.get(&0);
+        assert!(cache != pristine);
+    }
+
+    #[test]
+    fn views() {
+        use std::collections::HashSet;
+
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        for i in 0..10 {
+            cache.put(i, i * 2);
+        }
+
+        let keys: HashSet<isize> = cache.all_keys().copied().collect();
+        assert_eq!(keys, (0..10).collect());
+        let values: HashSet<isize> = cache.all_values().copied().collect();
+        assert_eq!(values, (0..10).map(|i| i * 2).collect());
+
+        let pristine = cache.clone();
+        assert_eq!(
+            cache.all_keys().collect::<Vec<_>>(),
+            pristine.all_keys().collect::<Vec<_>>()
+        );
+        assert!(cache == pristine);
+    }
+
+    #[test]
+    fn put_returns_old_value() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        assert_eq!(cache.put(0, 0), None);
+        assert_eq!(cache.put(0, 1), Some(0));
+        assert_eq!(cache.get(&0), Some(&1));
+    }
+
+    #[test]
+    fn swap() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        cache.put(0, 0);
+        assert_eq!(cache.swap(&0, 1), Some(0));
+        assert_eq!(cache.get(&0), Some(&1));
+
+        assert_eq!(cache.swap(&1, 10), None);
+        assert_eq!(cache.get(&1), Some(&10));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn take() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        cache.put(0, 0);
+        cache.put(1, 1);
+        cache.get(&0);
+        cache.get(&0);
+        // Promotes 0 to the main queue.
+        cache.put(2, 2);
+
+        assert_eq!(cache.take(&0), Some(0));
+        assert_eq!(cache.take(&2), Some(2));
+        assert_eq!(cache.take(&0), None);
+        assert_eq!(cache.len(), 0);
+        assert!(cache.get(&0).is_none());
+        assert!(cache.g_queue.peek(&0).is_none());
+
+        cache.put(0, 0);
+        assert_eq!(cache.s_queue.peek(&0).map(|obj| obj.get_freq()), Some(0));
+    }
+
+    #[test]
+    fn put_with_freq() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        cache.put_with_freq(0, 0, 2);
+        cache.put(1, 1);
+        assert!(cache.s_queue.peek(&0).is_some());
+
+        // Evicting the small queue promotes the pre-warmed entry.
+        cache.put(2, 2);
+        assert!(cache.m_queue.peek(&0).is_some());
+        assert!(cache.g_queue.peek(&1).is_some());
+        assert_eq!(cache.stats().promotions_to_main, 1);
+
+        assert_eq!(cache.put_with_freq(2, 20, 0), Some(2));
+        assert_eq!(cache.s_queue.peek(&2).map(|obj| obj.get_freq()), Some(0));
+    }
+
+    #[test]
+    fn eviction_order() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(3, 7, 7);
+        for i in 0..3 {
+            cache.put(i, i);
+            cache.get(&i);
+            cache.get(&i);
+        }
+        // Promotes 0..3 to main.
+        for i in 3..6 {
+            cache.put(i, i);
+        }
+        cache.get(&4);
+        cache.get(&4);
+        assert!(cache.m_queue.peek(&0).is_some());
+
+        let order: Vec<isize> = cache.iter_in_eviction_order()
+            .map(|(k, _)| *k)
+            .collect();
+        assert_eq!(order, vec![3, 5, 0, 1, 2]);
+
+        let mut evicted = Vec::new();
+        for _ in 0..order.len() {
+            let before: Vec<isize> = cache.all_values().copied().collect();
+            assert_eq!(cache.evict_n(1), 1);
+            let after: Vec<isize> = cache.all_values().copied().collect();
+            evicted.extend(before.into_iter().filter(|k| !after.contains(k)));
+        }
+        assert_eq!(evicted, order);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.m_queue.peek(&4).is_some());
+    }
+
+    #[test]
+    fn segment_info() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        for i in 0..2 {
+            cache.put(i, i);
+            cache.get(&i);
+            cache.get(&i);
+        }
+        // Promotes 0 and 1, then pushes 2 to ghost.
+        for i in 2..5 {
+            cache.put(i, i);
+        }
+
+        let info = cache.segment_info();
+        assert_eq!(info, SegmentInfo {
+            small_len: 2,
+            small_capacity: 2,
+            main_len: 2,
+            main_capacity: 8,
+            ghost_len: 1,
+            ghost_capacity: 8,
+        });
+        assert_eq!(info.small_len + info.main_len, cache.len());
+        assert_eq!(cache.occupancy_ratio(), 0.4);
+        assert_eq!(cache.small_occupancy(), 1.0);
+        assert_eq!(cache.main_occupancy(), 0.25);
+    }
+
+    #[test]
+    fn warm_from_iter() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        cache.warm_from_iter(vec![(0, 0, 0), (1, 1, 1), (2, 2, 2), (3, 3, 3)]);
+
+        assert_eq!(cache.s_queue.keys().copied().collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(cache.m_queue.keys().copied().collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(cache.m_queue.peek(&3).unwrap().get_freq(), 3);
+        assert!(cache.g_queue.is_empty());
+        assert_eq!(cache.len(), 4);
+
+        // Re-warming a cached key updates it in place.
+        cache.warm_from_iter(vec![(0, 10, 3)]);
+        assert_eq!(cache.s_queue.peek(&0).unwrap().get_freq(), 3);
+        assert_eq!(cache.get(&0), Some(&10));
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[test]
+    fn frequency_histogram() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        assert_eq!(cache.frequency_histogram(), [0; 4]);
+
+        for i in 0..8 {
+            cache.put(i, i);
+            for _ in 0..(i % 5) {
+                cache.get(&i);
+            }
+        }
+        // Accesses per key: 0, 1, 2, 3, 4, 0, 1, 2 (capped at 3).
+        assert_eq!(cache.frequency_histogram(), [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn reset_freq() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        cache.warm_from_iter(vec![(0, 0, 3), (1, 1, 3)]);
+        cache.put(2, 2);
+        cache.put(3, 3);
+        cache.put(4, 4);
+        assert!(cache.g_queue.peek(&2).is_some());
+
+        assert!(cache.reset_freq(&1));
+        assert!(!cache.reset_freq(&2));
+        assert!(!cache.reset_freq(&42));
+        assert_eq!(cache.peek(&1), Some(&1));
+
+        // Drains the small queue, then the main queue skips 0 (still hot)
+        // and evicts 1.
+        assert_eq!(cache.evict_n(3), 3);
+        assert!(cache.peek(&1).is_none());
+        assert_eq!(cache.peek(&0), Some(&0));
+    }
+
+    #[test]
+    fn push_to_value() {
+        let mut cache: S3FIFO<isize, Vec<&str>> = S3FIFO::try_new(10, 0.2).unwrap();
+        for item in ["a", "b", "c"] {
+            cache.push_to_value(42, item);
+        }
+        cache.push_to_value(7, "x");
+        assert_eq!(cache.peek(&42), Some(&vec!["a", "b", "c"]));
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(cache.pop_from_value(&42), Some("c"));
+        assert_eq!(cache.pop_from_value(&42), Some("b"));
+        assert_eq!(cache.pop_from_value(&42), Some("a"));
+        assert!(cache.peek(&42).is_none());
+        assert_eq!(cache.pop_from_value(&42), None);
+        assert_eq!(cache.len(), 1);
+        assert!(!cache.is_in_ghost(&42));
+    }
+
+    #[test]
+    fn get_shared() {
+        use std::sync::Arc;
+
+        let mut cache: S3FIFO<isize, Arc<Vec<u8>>> = S3FIFO::new_shared(NonZeroUsize::new(100).unwrap(), 0.1);
+        cache.put(0, Arc::new(vec![7; 1 << 20]));
+
+        let first = cache.get_shared(&0).unwrap();
+        assert_eq!(Arc::strong_count(&first), 2);
+        let second = cache.get_shared(&0).unwrap();
+        assert_eq!(Arc::strong_count(&first), 3);
+        assert!(Arc::ptr_eq(&first, &second));
+
+        assert!(cache.get_shared(&1).is_none());
+    }
+
+    #[test]
+    fn update_if_present() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        cache.warm_from_iter(vec![(0, 1, 0), (1, 10, 2)]);
+
+        assert!(cache.update_if_present(&0, |v| v * 2));
+        assert!(cache.update_if_present(&1, |v| v * 2));
+        assert_eq!(cache.peek(&0), Some(&2));
+        assert_eq!(cache.peek(&1), Some(&20));
+
+        assert!(!cache.update_if_present(&2, |_| unreachable!()));
+        assert!(cache.peek(&2).is_none());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn entry() {
+        use crate::entry::Entry;
+
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        *cache.entry(0).or_insert(10) += 1;
+        assert_eq!(cache.peek(&0), Some(&11));
+        assert_eq!(cache.s_queue.peek(&0).unwrap().get_freq(), 0);
+
+        *cache.entry(0).or_insert(10) += 1;
+        assert_eq!(cache.peek(&0), Some(&12));
+        assert_eq!(cache.s_queue.peek(&0).unwrap().get_freq(), 1);
+
+        assert_eq!(*cache.entry(1).or_default(), 0);
+        assert_eq!(*cache.entry(2).or_insert_with(|| 2), 2);
+        assert_eq!(cache.len(), 3);
+
+        match cache.entry(0) {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.get(), &12);
+                *entry.get_mut() = 13;
+                assert_eq!(entry.insert(14), 13);
+            }
+            Entry::Vacant(_) => panic!("0 should be cached"),
+        }
+        assert_eq!(cache.peek(&0), Some(&14));
+
+        match cache.entry(3) {
+            Entry::Vacant(entry) => assert_eq!(entry.into_key(), 3),
+            Entry::Occupied(_) => panic!("3 should not be cached"),
+        }
+        assert_eq!(cache.len(), 3);
+    }
+
+    /// A full capacity-50 cache: 45 hot entries in main, 5 cold in small.
+    fn full_cache(offset: isize) -> S3FIFO<isize, isize> {
+        let mut cache = S3FIFO::new_with_explicit_sizes(5, 45, 45);
+        let hot = (offset..offset + 45).map(|i| (i, i, 3));
+        let cold = (offset + 45..offset + 50).map(|i| (i, i, 0));
+        cache.warm_from_iter(hot.chain(cold));
+        assert_eq!(cache.len(), 50);
+        cache
+    }
+
+    #[test]
+    fn transfer() {
+        let mut dst = full_cache(0);
+        let mut src = full_cache(100);
+        dst.transfer(&mut src);
+
+        assert!(src.is_empty());
+        assert!(dst.len() <= 50);
+        assert_eq!(dst.peek(&144), Some(&144));
+        assert_eq!(dst.s_queue.peek(&144).unwrap().get_freq(), 0);
+    }
+
+    #[test]
+    fn transfer_with_freq() {
+        let mut dst = full_cache(0);
+        let mut src = full_cache(100);
+        dst.transfer_with_freq(&mut src);
+
+        assert!(src.is_empty());
+        assert!(dst.len() <= 50);
+        // Hot entries from `src` keep getting promoted, so most survive.
+        let moved = (100..145).filter(|k| dst.peek(k).is_some()).count();
+        assert!(moved >= 40, "only {} hot entries survived", moved);
+        assert_eq!(dst.s_queue.peek(&144).unwrap().get_freq(), 3);
+    }
+
+    /// The generator `sample_random_key` and `randomized_eviction_test`
+    /// take: a `rand::Rng`, or a closure without the `rand` feature.
+    #[cfg(feature = "rand")]
+    fn sampling_rng(seed: u64) -> impl rand::Rng {
+        use rand::SeedableRng;
+        rand::rngs::StdRng::seed_from_u64(seed)
+    }
+
+    #[cfg(not(feature = "rand"))]
+    fn sampling_rng(seed: u64) -> impl FnMut() -> u64 {
+        let mut rng = crate::test_utils::XorShift::new(seed);
+        move || rng.next_u64()
+    }
+
+    #[test]
+    fn workload_simulation_hit_rate() {
+        use crate::test_utils::XorShift;
+
+        let mut rng = XorShift::new(7);
+        let mut next = || rng.next_u64();
+        let mut sampler = sampling_rng(7);
+
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(20, 0.1).unwrap();
+        assert_eq!(cache.randomized_eviction_test(100, &mut sampler), 0.0);
+
+        for i in 0..50 {
+            cache.put(i, i);
+            cache.get(&i);
+            cache.get(&i);
+        }
+        assert_eq!(cache.randomized_eviction_test(1000, &mut sampler), 1.0);
+
+        let cached: Vec<isize> = cache.all_keys().take(cache.len()).copied().collect();
+        let absent: Vec<isize> = (100..100 + cached.len() as isize).collect();
+        assert_eq!(cache.workload_simulation_hit_rate(&absent, 100, &mut next), 0.0);
+        assert_eq!(cache.workload_simulation_hit_rate(&[], 100, &mut next), 0.0);
+
+        let mixed: Vec<isize> = cached.iter().chain(absent.iter()).copied().collect();
+        let rate = cache.workload_simulation_hit_rate(&mixed, 10_000, &mut next);
+        assert!((rate - 0.5).abs() < 0.05, "hit rate {}", rate);
+        assert_eq!(cache.len(), cached.len());
+    }
+
+    #[test]
+    fn sample_random_key() {
+        use std::collections::HashSet;
+
+        let mut next = sampling_rng(7);
+
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        assert!(cache.sample_random_key(&mut next).is_none());
+
+        cache.warm_from_iter((0..4).map(|i| (i, i, 3)));
+        for i in 4..10 {
+            cache.put(i, i);
+        }
+        let ghosts: HashSet<isize> = cache.g_queue.keys().copied().collect();
+        assert!(!ghosts.is_empty());
+
+        let mut seen = HashSet::new();
+        for _ in 0..200 {
+            let key = *cache.sample_random_key(&mut next).unwrap();
+            assert!(cache.peek(&key).is_some());
+            assert!(!ghosts.contains(&key));
+            seen.insert(key);
+        }
+        assert_eq!(seen.len(), cache.len());
+
+        let (key, value) = cache.sample_random_entry(&mut next).unwrap();
+        assert_eq!(key, value);
+    }
+
+    /// Insert 0..50, reading every key once.
+    fn read_once_workload(cache: &mut S3FIFO<isize, isize>) {
+        for i in 0..50 {
+            cache.put(i, i);
+            cache.get(&i);
+        }
+    }
+
+    #[test]
+    fn freq_promote_threshold() {
+        let mut default: S3FIFO<isize, isize> = S3FIFO::try_new(20, 0.2).unwrap();
+        assert_eq!(default.freq_promote_threshold(), 1);
+        read_once_workload(&mut default);
+        assert_eq!(default.segment_info().main_len, 0);
+
+        let mut eager: S3FIFO<isize, isize> = S3FIFO::new_with_config(20, 0.2, 0);
+        read_once_workload(&mut eager);
+        assert_eq!(eager.segment_info().main_len, 16);
+
+        let mut strict: S3FIFO<isize, isize> = S3FIFO::new_with_config(20, 0.2, 3);
+        for i in 0..50 {
+            strict.put(i, i);
+            for _ in 0..5 {
+                strict.get(&i);
+            }
+        }
+        assert_eq!(strict.segment_info().main_len, 0);
+        assert_eq!(strict.stats().promotions_to_main, 0);
+    }
+
+    #[test]
+    fn top_k_by_freq() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        assert!(cache.top_k_by_freq(3).is_empty());
+
+        let reads = [0, 2, 1, 3, 0, 2, 1];
+        for (key, n) in reads.iter().enumerate() {
+            let key = key as isize;
+            cache.put(key, key);
+            for _ in 0..*n {
+                cache.get(&key);
+            }
+        }
+
+        assert_eq!(cache.top_k_by_freq(3), vec![(&3, 3), (&1, 2), (&5, 2)]);
+        assert!(cache.top_k_by_freq(0).is_empty());
+        assert_eq!(cache.top_k_by_freq(100).len(), 7);
+    }
+
+    #[test]
+    fn sorted_by_freq() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        let reads = [0, 2, 1, 3, 0, 2, 1];
+        for (key, n) in reads.iter().enumerate() {
+            let key = key as isize;
+            cache.put(key, key * 10);
+            for _ in 0..*n {
+                cache.get(&key);
+            }
+        }
+
+        let expected = vec![
+            (3, 30, 3), (1, 10, 2), (5, 50, 2), (2, 20, 1), (6, 60, 1), (0, 0, 0), (4, 40, 0),
+        ];
+        assert_eq!(cache.sorted_by_freq_cloned(), expected);
+        assert_eq!(cache.into_sorted_by_freq(), expected);
+    }
+
+    #[test]
+    fn print_dot() {
+        let mut cache: S3FIFO<String, isize> = S3FIFO::new_with_explicit_sizes(2, 4, 4);
+        for i in 0..6 {
+            let key = format!("k\"{}", i);
+            cache.put(key.clone(), i);
+            if i % 2 == 0 {
+                cache.get(&key);
+                cache.get(&key);
+            }
+        }
+        assert!(!cache.g_queue.is_empty() && !cache.m_queue.is_empty());
+
+        let dot = cache.print_dot();
+        assert!(std::str::from_utf8(dot.as_bytes()).is_ok());
+        assert!(dot.starts_with("digraph"));
+        for name in ["\"small\"", "\"main\"", "\"ghost\""] {
+            assert!(dot.contains(name), "{} missing from\n{}", name, dot);
+        }
+        assert!(dot.contains("style=dashed"));
+        assert!(dot.contains(r#"label="\"k\\\"0\" (freq"#));
+        assert!(dot.contains("small_0 -> small_1;"));
+    }
+
+    #[test]
+    fn get_multi_mut() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 4, 4);
+        for i in 0..4 {
+            cache.put(i, i * 10);
+            cache.get(&i);
+            cache.get(&i);
+        }
+        assert!(!cache.s_queue.is_empty() && !cache.m_queue.is_empty());
+
+        let keys = [3, 0, 100, 2];
+        for value in cache.get_multi_mut(&keys).into_iter().flatten() {
+            *value += 1;
+        }
+        assert!(cache.get_multi_mut(&keys)[2].is_none());
+        assert_eq!(cache.peek(&0), Some(&1));
+        assert_eq!(cache.peek(&2), Some(&21));
+        assert_eq!(cache.peek(&3), Some(&31));
+        assert_eq!(cache.peek(&1), Some(&10));
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate keys")]
+    fn get_multi_mut_rejects_duplicates() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(10, 0.1).unwrap();
+        cache.put(1, 1);
+        cache.get_multi_mut(&[1, 2, 1]);
+    }
+
+    #[test]
+    fn collect_garbage() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(1, 1, 3);
+        for i in 0..5 {
+            cache.put(i, i);
+        }
+        cache.g_queue.assert_invariants("ghost");
+        assert_eq!(cache.collect_garbage(), 0);
+
+        // Re-inserting into the full ring buffer duplicates a key and
+        // overwrites the oldest one, which stays in the hash table.
+        let ghosts: Vec<isize> = cache.g_queue.keys().copied().collect();
+        assert_eq!(ghosts.len(), 3);
+        cache.g_queue.insert(ghosts[2], ());
+        assert!(cache.g_queue.peek(&ghosts[0]).is_some());
+
+        assert_eq!(cache.collect_garbage(), 2);
+        cache.g_queue.assert_invariants("ghost");
+        assert!(cache.g_queue.peek(&ghosts[0]).is_none());
+        assert_eq!(cache.g_queue.keys().copied().collect::<Vec<_>>(), vec![ghosts[1], ghosts[2]]);
+        assert_eq!(cache.collect_garbage(), 0);
+    }
+
+    #[test]
+    fn drain_to_vec_with_priority() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 4, 8);
+        for i in 0..8 {
+            cache.put(i, i);
+            if i < 2 {
+                cache.get(&i);
+                cache.get(&i);
+            }
+        }
+        assert!(!cache.m_queue.is_empty() && !cache.g_queue.is_empty());
+        // A duplicate slot yields nothing when evicted; the keys after it
+        // must still be cleared.
+        let ghost = *cache.g_queue.keys().next().unwrap();
+        cache.g_queue.insert(ghost, ());
+        cache.g_queue.insert(100, ());
+        assert!(cache.g_queue.len() < cache.g_queue.capacity());
+
+        let (main, small) = cache.drain_to_vec_with_priority();
+        assert!(!main.is_empty() && !small.is_empty());
+        assert!(main.contains(&(0, 0)) && main.contains(&(1, 1)));
+        assert!(small.contains(&(7, 7)));
+        assert!(cache.is_empty());
+        assert!(cache.g_queue.is_empty());
+        assert!(cache.get(&0).is_none());
+
+        cache.insert_batch_with_priority(main, small);
+        assert!(cache.m_queue.peek(&0).is_some() && cache.m_queue.peek(&1).is_some());
+        assert_eq!(cache.s_queue.peek(&7).map(|obj| *obj.get_value()), Some(7));
+    }
+
+    #[test]
+    fn eviction_distance() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(50, 0.1).unwrap();
+        for i in 0..5 {
+            cache.put(i, i);
+        }
+        for i in 0..5 {
+            assert_eq!(cache.eviction_distance(&i), Some(i as usize));
+        }
+        assert_eq!(cache.get_insertion_order(&9), None);
+
+        // 0 is promoted to the front of the main queue, 1 is evicted.
+        cache.get(&0);
+        cache.get(&0);
+        assert_eq!(cache.eviction_distance(&0), Some(0));
+        cache.put(5, 5);
+        assert!(cache.m_queue.peek(&0).is_some());
+        assert_eq!(cache.eviction_distance(&0), Some(0));
+        assert_eq!(cache.eviction_distance(&1), None);
+        assert_eq!(cache.eviction_distance(&2), Some(0));
+        assert_eq!(cache.eviction_distance(&5), Some(3));
+    }
+
+    #[test]
+    fn ghost_keys() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(1, 2, 2);
         cache.put(0, 0);
+        cache.get(&0);
+        cache.put(1, 1);
+        assert!(cache.is_in_ghost(&0));
+        assert!(!cache.is_in_ghost(&1));
+        assert_eq!(cache.iter_ghost_keys().collect::<Vec<_>>(), vec![&0]);
 
-        assert_eq!(cache.size, 1);
-        assert_eq!(cache.s_queue.len(), 1);
-        assert_eq!(cache.m_queue.len(), 0);
-        assert_eq!(cache.g_queue.len(), 0);
+        // Two more evictions push 0 out of the ghost queue.
+        cache.put(2, 2);
+        cache.put(3, 3);
+        assert_eq!(cache.iter_ghost_keys().collect::<Vec<_>>(), vec![&1, &2]);
+        assert!(!cache.is_in_ghost(&0));
 
-        cache.put(0, 1);
-        assert_eq!(cache.size, 1);
-        assert_eq!(cache.s_queue.len(), 1);
-        assert_eq!(cache.m_queue.len(), 0);
-        assert_eq!(cache.g_queue.len(), 0);
+        let disabled: S3FIFO<isize, isize> = S3FIFO::with_ghost_disabled(10, 0.1);
+        assert!(!disabled.is_in_ghost(&0));
     }
 
     #[test]
-    fn simple_put_get() {
-        let mut cache: S3FIFO<isize, isize> = S3FIFO::new(100, 0.1);
+    fn insert_at_tail_of_main() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(1, 4, 4);
+        for i in 0..4 {
+            cache.insert_at_tail_of_main(i, i, 0);
+        }
+        assert_eq!(cache.insert_at_tail_of_main(100, 100, 0), None);
+        assert_eq!(cache.m_queue.keys().copied().collect::<Vec<_>>(), vec![1, 2, 3, 100]);
+
+        // Every entry inserted before it is evicted first.
+        for i in 4..7 {
+            cache.insert_at_tail_of_main(i, i, 0);
+        }
+        assert_eq!(cache.m_queue.keys().copied().collect::<Vec<_>>(), vec![100, 4, 5, 6]);
+
+        // A cached key moves to the tail with its new frequency.
+        cache.put(7, 7);
+        assert_eq!(cache.insert_at_tail_of_main(7, 70, 3), Some(7));
+        assert!(cache.s_queue.is_empty());
+        assert_eq!(cache.m_queue.keys().last(), Some(&7));
+        assert_eq!(cache.m_queue.peek(&7).unwrap().get_freq(), 3);
+        assert_eq!(cache.len(), 4);
+        cache.assert_invariants();
+    }
+
+    #[test]
+    fn eviction_pressure() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        assert_eq!(cache.get_eviction_pressure(), 0.0);
+
         cache.put(0, 0);
-        let result = cache.get(&0);
-        assert_eq!(result, Some(&0));
+        cache.put(1, 1);
+        assert!((cache.get_eviction_pressure() - 0.4).abs() < 1e-9);
+
+        cache.warm_from_iter((10..14).map(|i| (i, i, 3)));
+        assert!((cache.get_eviction_pressure() - 0.7).abs() < 1e-9);
+        assert_eq!(cache.time_to_eviction(&12), Some(2));
+        assert_eq!(cache.time_to_eviction(&1), Some(1));
+        assert_eq!(cache.time_to_eviction(&99), None);
+
+        for i in 20..40 {
+            cache.put(i, i);
+            let pressure = cache.get_eviction_pressure();
+            assert!((0.0..=1.0).contains(&pressure));
+        }
+
+        let mut full: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        full.warm_from_iter((0..8).map(|i| (i, i, 3)));
+        full.put(8, 8);
+        full.put(9, 9);
+        assert!(full.is_full());
+        assert_eq!(full.get_eviction_pressure(), 1.0);
+
+        let mut lru: S3FIFO<isize, isize> = S3FIFO::with_strategy(4, 0.1, EvictionStrategy::LRU);
+        lru.put(0, 0);
+        assert_eq!(lru.get_eviction_pressure(), 0.25);
     }
 
     #[test]
-    fn multiple_put_get() {
-        let mut cache: S3FIFO<isize, isize> = S3FIFO::new(100, 0.1);
-        for i in 0..50 {
+    fn lru_approximation() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(3, 4, 4);
+        assert_eq!(cache.get_lru_approximation(), None);
+        assert_eq!(cache.get_mru_approximation(), None);
+
+        for i in 0..3 {
             cache.put(i, i);
-            let result = cache.get(&i);
-            assert_eq!(result, Some(&i));
+        }
+        assert_eq!(cache.get_lru_approximation(), Some(&0));
+        assert_eq!(cache.get_mru_approximation(), Some(&2));
+
+        // 0 is read twice, so the next insert promotes it and evicts 1.
+        cache.get(&0);
+        cache.get(&0);
+        assert_eq!(cache.get_k_lru_candidates(2), vec![&1, &2]);
+        cache.put(3, 3);
+        assert_eq!(cache.get_lru_approximation(), Some(&2));
+        assert_eq!(cache.get_mru_approximation(), Some(&0));
+        assert_eq!(cache.get_k_lru_candidates(10), vec![&2, &3, &0]);
+        assert!(cache.get_k_lru_candidates(0).is_empty());
+    }
+
+    #[test]
+    fn remove_by_value() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 4, 4);
+        cache.warm_from_iter([(0, 7, 2), (1, 10, 2)]);
+        cache.put(2, 7);
+        cache.put(3, 30);
+
+        // The small queue is searched first.
+        assert_eq!(cache.remove_by_value(&7), Some((2, 7)));
+        assert_eq!(cache.remove_by_value(&7), Some((0, 7)));
+        assert_eq!(cache.remove_by_value(&7), None);
+        assert_eq!(cache.remove_by_value(&10), Some((1, 10)));
+        assert_eq!(cache.len(), 1);
+        assert!(cache.g_queue.is_empty());
+        cache.assert_invariants();
+    }
+
+    #[test]
+    fn get_by_predicate() {
+        let mut cache: S3FIFO<String, isize> = S3FIFO::try_new(20, 0.2).unwrap();
+        for (i, key) in ["a_1", "b_1", "a_2"].into_iter().enumerate() {
+            cache.put(key.to_string(), i as isize);
+        }
+        let prefixed = |prefix: &'static str| move |key: &String| key.starts_with(prefix);
+
+        assert_eq!(cache.get_key_by_predicate(prefixed("a_")).map(String::as_str), Some("a_1"));
+        assert_eq!(cache.stats().hits, 0);
+        assert_eq!(cache.get_by_predicate(prefixed("a_")), Some(&0));
+        assert_eq!(cache.get_by_predicate(prefixed("b_")), Some(&1));
+        assert_eq!(cache.get_by_predicate(prefixed("c_")), None);
+        assert_eq!(cache.stats().hits, 2);
+        assert_eq!(cache.s_queue.peek(&"a_1".to_string()).unwrap().get_freq(), 1);
+    }
+
+    #[test]
+    fn for_each_mut() {
+        let mut cache: S3FIFO<isize, i32> = S3FIFO::new_with_explicit_sizes(2, 4, 4);
+        cache.warm_from_iter((0..3).map(|i| (i, i as i32, 2)));
+        cache.put(3, 3);
+        cache.put(4, 4);
+        assert!(!cache.s_queue.is_empty() && !cache.m_queue.is_empty());
+
+        cache.for_each_mut(|_, value| *value *= 2);
+        assert_eq!(cache.m_queue.peek(&0).unwrap().get_freq(), 2);
+        for key in 0..5 {
+            assert_eq!(cache.get(&key), Some(&(key as i32 * 2)));
+        }
+
+        let mut visited = Vec::new();
+        cache.for_each(|key, value| visited.push((*key, *value)));
+        assert_eq!(visited, vec![(3, 6), (4, 8), (0, 0), (1, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn is_almost_full() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        cache.warm_from_iter((0..89).map(|i| (i, i, 2)));
+        assert_eq!(cache.count(), 89);
+        assert!((cache.fill_ratio() - 0.89).abs() < 1e-9);
+        assert!(!cache.is_almost_full(0.9));
+
+        cache.put(89, 89);
+        assert_eq!(cache.count(), 90);
+        assert!(cache.is_almost_full(0.9));
+        assert!(!cache.is_almost_full(1.0));
+    }
+
+    #[test]
+    fn discard_cold_entries() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(4, 4, 8);
+        cache.warm_from_iter([(10, 10, 2), (11, 11, 3)]);
+        for i in 0..4 {
+            cache.put(i, i);
+            for _ in 0..i {
+                cache.get(&i);
+            }
+        }
+        assert_eq!(cache.discard_cold_entries(0), 0);
+
+        assert_eq!(cache.discard_cold_entries(1), 1);
+        assert_eq!(cache.iter_ghost_keys().collect::<Vec<_>>(), vec![&0]);
+
+        // Main queue entries are dropped without a ghost entry.
+        assert_eq!(cache.discard_cold_entries(3), 3);
+        assert_eq!(cache.iter_ghost_keys().collect::<Vec<_>>(), vec![&0, &1, &2]);
+        assert!(!cache.is_in_ghost(&10));
+        assert_eq!(cache.all_keys().take(cache.len()).collect::<Vec<_>>(), vec![&3, &11]);
+        cache.assert_invariants();
+    }
+
+    #[test]
+    fn eviction_counters() {
+        let mut cache: S3FIFO<i32, i32> = S3FIFO::new_with_explicit_sizes(1, 1, 4);
+        for key in 0..4 {
+            cache.put(key, key);
+        }
+        // Cold entries leave the small queue into the ghost queue.
+        assert_eq!(cache.stats().evictions_to_ghost, 3);
+        assert_eq!(cache.stats().full_evictions, 0);
+        assert!(cache.stats().to_string().contains("evictions_to_ghost=3"));
+    }
+
+    #[test]
+    fn read_only_view() {
+        let mut cache: S3FIFO<i32, i32> = S3FIFO::new_with_explicit_sizes(1, 1, 4);
+        for key in 0..3 {
+            cache.put(key, key * 10);
+        }
+        let before = cache.clone();
+        let view: ReadOnlyS3FIFO<i32, i32> = cache.as_read_only();
+
+        assert_eq!(view.len(), cache.len());
+        assert!(!view.is_empty());
+        assert_eq!(view.peek(&2), Some(&20));
+        assert!(view.contains_key(&2));
+        assert!(!view.contains_key(&0));
+        assert_eq!(view.keys().copied().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(view.values().copied().collect::<Vec<_>>(), vec![20]);
+        assert_eq!(view.iter_ghost_keys().copied().collect::<Vec<_>>(), vec![0, 1]);
+        // Lookups through the view leave frequencies and stats alone.
+        assert!(cache == before);
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    #[test]
+    fn get_or_insert_default() {
+        let mut cache: S3FIFO<&str, usize> = S3FIFO::new_with_explicit_sizes(2, 2, 2);
+        assert_eq!(cache.get_or_insert_default("missing"), &0);
+        assert_eq!(cache.len(), 1);
+
+        *cache.get_mut_or_insert_default("counter") += 5;
+        assert_eq!(cache.get_mut_or_insert_default("counter"), &mut 5);
+        assert_eq!(cache.get_or_insert_default("counter"), &5);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn per_queue_eviction_counters() {
+        let mut cache: S3FIFO<i32, i32> = S3FIFO::new_with_explicit_sizes(1, 1, 4);
+        // 0 is read twice, so it is promoted when 1 needs its slot.
+        cache.put(0, 0);
+        cache.get(&0);
+        cache.get(&0);
+        cache.put(1, 1);
+        // 1 is cold and goes to the ghost queue, 2 takes its place.
+        cache.put(2, 2);
+        // 2 is hot too, so its promotion pushes 0 (freq 0) out of main.
+        cache.get(&2);
+        cache.get(&2);
+        cache.put(3, 3);
+
+        let stats = cache.stats();
+        assert_eq!(stats.promotions_to_main, 2);
+        assert_eq!(stats.evictions_to_ghost, 1);
+        assert_eq!(stats.main_evictions, 1);
+        assert_eq!(stats.full_evictions, 1);
+        assert_eq!(stats.small_evictions, 1);
+        assert_eq!(stats.small_eviction_promotion_rate(), 2.0 / 3.0);
+
+        // Without a ghost queue, small queue drops still count against
+        // promotions.
+        let mut cache: S3FIFO<i32, i32> = S3FIFO::with_ghost_disabled(4, 0.5);
+        cache.put(0, 0);
+        cache.get(&0);
+        cache.get(&0);
+        for key in 1..4 {
+            cache.put(key, key);
+        }
+        let stats = cache.stats();
+        assert_eq!(stats.promotions_to_main, 1);
+        assert_eq!(stats.evictions_to_ghost, 0);
+        assert_eq!(stats.small_evictions, 1);
+        assert_eq!(stats.small_eviction_promotion_rate(), 0.5);
+    }
+
+    #[test]
+    fn ghost_keys_are_unique() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(1, 1, 4);
+        cache.put(0, 0);
+        cache.put(1, 1);
+        // 0 is back in the small queue while still remembered as a ghost.
+        cache.warm_from_iter([(0, 0, 0)]);
+        cache.put(2, 2);
+        assert_eq!(cache.iter_ghost_keys().copied().collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(cache.segment_info().ghost_len, 2);
+
+        // A ghost hit caches the key again; its ghost slot ages out
+        // rather than being duplicated when the key is retired again.
+        cache.put(1, 1);
+        assert!(cache.peek(&1).is_some());
+        cache.put(3, 3);
+        cache.put(4, 4);
+        assert_eq!(cache.iter_ghost_keys().filter(|&&key| key == 1).count(), 1);
+        cache.assert_invariants();
+    }
+
+    #[test]
+    fn overfill_keeps_size() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        for i in 0..1000 {
+            cache.put(i, i);
+            if i % 3 == 0 {
+                // Hot keys get promoted to the main queue.
+                cache.get(&i);
+                cache.get(&i);
+            }
+            assert!(cache.size <= cache.cache_size);
+            assert_eq!(cache.size, cache.s_queue.len() + cache.m_queue.len());
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "eviction made no progress after 20 iterations")]
+    fn eviction_stuck() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(1, 9, 9);
+        // Corrupt the bookkeeping: claim to be full with empty queues.
+        cache.size = cache.cache_size;
+        cache.put(0, 0);
+    }
+
+    #[test]
+    fn queue_hit_stats() {
+        // 2 small slots, 8 main slots.
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        cache.put(0, 0);
+        cache.put(1, 1);
+        cache.get(&0);
+        cache.get(&0);
+        // Pushes 0 (freq 2) to main and 1 (freq 0) to ghost.
+        cache.put(2, 2);
+        cache.put(3, 3);
+
+        assert_eq!(cache.stats().promotions_to_main, 1);
+        assert_eq!(cache.stats().small_queue_hits, 2);
+        assert_eq!(cache.stats().main_queue_hits, 0);
+
+        cache.get(&0);
+        cache.get(&2);
+        cache.get(&42);
+
+        let stats = cache.stats();
+        assert_eq!(stats.small_queue_hits, 3);
+        assert_eq!(stats.main_queue_hits, 1);
+        assert_eq!(stats.hits, 4);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.small_hit_rate(), 0.6);
+        assert_eq!(stats.main_hit_rate(), 0.2);
+    }
+
+    #[test]
+    fn ghost_tracks_keys_only() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        for i in 0..5 {
+            cache.put(i, i);
+        }
+
+        // 0..3 were pushed out of the small queue without being read.
+        for i in 0..3 {
+            assert!(cache.g_queue.peek(&i).is_some());
+            assert!(cache.get(&i).is_none());
+        }
+        assert_eq!(cache.len(), 2);
+
+        cache.shrink_ghost();
+        assert_eq!(cache.g_queue.len(), 3);
+
+        // A ghost hit goes straight to the main queue.
+        cache.put(0, 0);
+        assert!(cache.m_queue.peek(&0).is_some());
+    }
+
+    #[test]
+    fn ghost_disabled() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::with_ghost_disabled(10, 0.2);
+        assert!(!cache.ghost_enabled());
+
+        for i in 0..5 {
+            cache.put(i, i);
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(cache.g_queue.is_empty());
+
+        // Without ghost history a re-inserted key starts in the small queue.
+        cache.put(0, 0);
+        assert!(cache.s_queue.peek(&0).is_some());
+        assert!(cache.m_queue.is_empty());
+
+        // Promotion to main still works.
+        cache.get(&0);
+        cache.get(&0);
+        for i in 10..20 {
+            cache.put(i, i);
+            assert_eq!(cache.len(), cache.s_queue.len() + cache.m_queue.len());
+        }
+        assert_eq!(cache.get(&0), Some(&0));
+        assert!(cache.m_queue.peek(&0).is_some());
+        assert!(cache.g_queue.is_empty());
+    }
+
+    #[test]
+    fn compact() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::try_new(100, 0.1).unwrap();
+        for i in 0..10 {
+            cache.put(i, i);
+            cache.get(&i);
+            cache.get(&i);
+        }
+        // Moves all hot entries out of the small queue into main.
+        cache.put(10, 10);
+
+        let before = cache.memory_overhead_estimate();
+        let pristine = cache.clone();
+        cache.compact();
+        assert!(cache.memory_overhead_estimate() < before);
+        assert!(cache == pristine);
+        for i in 0..=10 {
+            assert_eq!(cache.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn get_or_evict() {
+        let mut cache: S3FIFO<isize, isize> = S3FIFO::new_with_explicit_sizes(2, 8, 8);
+        for i in 0..10 {
+            assert_eq!(cache.get_or_evict(i, || i * 10), &(i * 10));
+        }
+        assert_eq!(cache.stats().misses, 10);
+
+        // Hit: the closure is not used.
+        assert_eq!(cache.get_or_evict(9, || unreachable!()), &90);
+        assert_eq!(cache.stats().hits, 1);
+
+        for i in 10..30 {
+            assert_eq!(cache.get_or_evict(i, || -i), &-i);
+            assert!(cache.size <= cache.cache_size);
+        }
+    }
+}
+
+///
+/// Property tests. proptest shrinks a failing case to a minimal cache
+/// configuration and operation sequence before reporting it.
+#[cfg(test)]
+mod prop_tests {
+    use std::collections::{HashMap, HashSet};
+    use proptest::prelude::*;
+    use super::S3FIFO;
+
+    const CASES: u32 = 1000;
+    const KEY_SPACE: usize = 150;
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Put(usize),
+        Get(usize),
+        PutWithFreq(usize, usize),
+        Take(usize),
+        EvictN(usize),
+    }
+
+    /// `(cache_size, small queue capacity)`: capacity in `2..=50` with at
+    /// least one slot in each queue.
+    fn config() -> impl Strategy<Value = (usize, usize)> {
+        (2usize..=50).prop_flat_map(|cache_size| (Just(cache_size), 1..cache_size))
+    }
+
+    fn new_cache(cache_size: usize, small: usize) -> S3FIFO<usize, usize> {
+        let ratio = (small as f64 + 0.5) / cache_size as f64;
+        S3FIFO::try_new(cache_size, ratio).unwrap()
+    }
+
+    fn put_or_get() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..KEY_SPACE).prop_map(Op::Put),
+            (0..KEY_SPACE).prop_map(Op::Get),
+        ]
+    }
+
+    fn any_op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            2 => (0..KEY_SPACE).prop_map(Op::Put),
+            1 => (0..KEY_SPACE).prop_map(Op::Get),
+            1 => (0..KEY_SPACE, 0..4usize).prop_map(|(key, freq)| Op::PutWithFreq(key, freq)),
+            1 => (0..KEY_SPACE).prop_map(Op::Take),
+            1 => (0..3usize).prop_map(Op::EvictN),
+        ]
+    }
+
+    fn apply(cache: &mut S3FIFO<usize, usize>, op: &Op) {
+        match *op {
+            Op::Put(key) => { cache.put(key, key); }
+            Op::Get(key) => { cache.get(&key); }
+            Op::PutWithFreq(key, freq) => { cache.put_with_freq(key, key, freq); }
+            Op::Take(key) => { cache.take(&key); }
+            Op::EvictN(n) => { cache.evict_n(n); }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+        /// Unread keys are bounded by the small queue, so "N unique keys
+        /// fit in a capacity-N cache" only holds up to its capacity.
+        #[test]
+        fn unique_keys_within_small_capacity_are_findable(
+            (cache_size, small) in config(),
+            n in any::<prop::sample::Index>(),
+            offset in 0..1000usize,
+        ) {
+            let mut cache = new_cache(cache_size, small);
+            let n = 1 + n.index(small);
+            for key in offset..offset + n {
+                cache.put(key, key);
+            }
+            for key in offset..offset + n {
+                prop_assert_eq!(cache.get(&key), Some(&key));
+            }
+        }
+
+        #[test]
+        fn size_never_exceeds_capacity(
+            (cache_size, small) in config(),
+            ops in prop::collection::vec(put_or_get(), 0..200),
+        ) {
+            let mut cache = new_cache(cache_size, small);
+            for op in &ops {
+                apply(&mut cache, op);
+                prop_assert!(cache.len() <= cache.cache_size);
+            }
+        }
+
+        #[test]
+        fn len_matches_unique_cached_keys(
+            (cache_size, small) in config(),
+            ops in prop::collection::vec(any_op(), 0..200),
+        ) {
+            let mut cache = new_cache(cache_size, small);
+            for op in &ops {
+                apply(&mut cache, op);
+                let small: HashSet<&usize> = cache.s_queue.keys().collect();
+                let main: HashSet<&usize> = cache.m_queue.keys().collect();
+                prop_assert!(small.is_disjoint(&main));
+                prop_assert_eq!(cache.len(), small.len() + main.len());
+            }
+        }
+
+        #[test]
+        fn invariants_hold_after_every_operation(
+            (cache_size, small) in config(),
+            ops in prop::collection::vec(any_op(), 0..200),
+        ) {
+            let mut cache = new_cache(cache_size, small);
+            for op in &ops {
+                apply(&mut cache, op);
+                cache.assert_invariants();
+            }
+        }
+
+        /// Entries only join the small queue at its back, and reads don't
+        /// move them, so it stays in the order its keys were inserted.
+        #[test]
+        fn small_queue_keeps_insertion_order(
+            (cache_size, small) in config(),
+            ops in prop::collection::vec(put_or_get(), 0..200),
+        ) {
+            let mut cache = new_cache(cache_size, small);
+            let mut inserted_at = HashMap::new();
+            for (step, op) in ops.iter().enumerate() {
+                if let Op::Put(key) = *op {
+                    if cache.peek(&key).is_none() {
+                        inserted_at.insert(key, step);
+                    }
+                }
+                apply(&mut cache, op);
+
+                let order: Vec<usize> = cache.s_queue.keys().map(|key| inserted_at[key]).collect();
+                prop_assert!(order.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", order);
+            }
+        }
+
+        #[test]
+        fn unread_keys_are_evicted_in_fifo_order(
+            (cache_size, small) in config(),
+            n in 1..200usize,
+        ) {
+            let mut cache = new_cache(cache_size, small);
+            for key in 0..n {
+                cache.put(key, key);
+            }
+
+            // Nothing was read, so nothing is promoted: the survivors must be
+            // exactly the most recently inserted keys.
+            let survivors: Vec<usize> = cache.all_values().copied().collect();
+            let expected_start = n.saturating_sub(cache.small_cache_capacity);
+            prop_assert_eq!(survivors, (expected_start..n).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn get_returns_latest_put(
+            (cache_size, small) in config(),
+            ops in prop::collection::vec((any::<bool>(), 0..100usize), 0..200),
+        ) {
+            let mut cache = new_cache(cache_size, small);
+            let mut model = HashMap::new();
+            for (step, &(is_put, key)) in ops.iter().enumerate() {
+                if is_put {
+                    cache.put(key, step);
+                    model.insert(key, step);
+                    // A fresh put is always readable right away.
+                    prop_assert_eq!(cache.get(&key), Some(&step));
+                } else if let Some(value) = cache.get(&key) {
+                    prop_assert_eq!(Some(value), model.get(&key));
+                }
+            }
+        }
+
+        #[test]
+        fn nearest_eviction_candidate_is_evicted_next(
+            (cache_size, small) in config(),
+            ops in prop::collection::vec(put_or_get(), 0..200),
+        ) {
+            let mut cache = new_cache(cache_size, small);
+            for op in &ops {
+                apply(&mut cache, op);
+            }
+
+            let candidate = cache.nearest_eviction_candidate().copied();
+            let promotions = cache.stats().promotions_to_main;
+            let before: Vec<usize> = cache.snapshot().into_iter().map(|(key, _, _)| key).collect();
+            cache.evict_n(1);
+            // Promotions overflowing the main queue evict from it first;
+            // see the docs.
+            let overflowed = cache.m_queue.len() == cache.main_cache_capacity
+                && cache.stats().promotions_to_main > promotions;
+            if !overflowed {
+                let evicted = before.into_iter().find(|key| cache.peek(key).is_none());
+                prop_assert_eq!(candidate, evicted);
+            }
         }
     }
 }
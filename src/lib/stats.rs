@@ -0,0 +1,200 @@
+use std::fmt::{self, Write};
+
+///
+/// Counters collected by `S3FIFO` while serving requests.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub small_queue_hits: u64,
+    pub main_queue_hits: u64,
+    /// Entries moved from the small queue to the main queue on eviction,
+    /// i.e. the small queue evictions that were promoted.
+    pub promotions_to_main: u64,
+    /// Inserts of a key still remembered by the ghost queue.
+    pub ghost_hits: u64,
+    /// Inserts of a key the ghost queue doesn't know.
+    pub ghost_misses: u64,
+    /// Entries inserted through `S3FIFO::prefetch`.
+    pub prefetch_count: u64,
+    /// Evicted entries whose key went to the ghost queue. Only the small
+    /// queue retires keys to the ghost queue, so these are the small queue
+    /// evictions to ghost.
+    pub evictions_to_ghost: u64,
+    /// Evicted entries dropped without a ghost entry, e.g. from the main
+    /// queue.
+    pub full_evictions: u64,
+    /// Entries evicted from the main queue, a subset of `full_evictions`.
+    pub main_evictions: u64,
+    /// Entries evicted from the small queue instead of promoted, whether
+    /// or not their key went to the ghost queue.
+    pub small_evictions: u64,
+}
+
+impl CacheStats {
+    #[inline(always)]
+    pub fn lookups(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        Self::ratio(self.hits, self.lookups())
+    }
+
+    /// Share of lookups answered by the small queue.
+    /// A disproportionately high value suggests the small queue is too large.
+    pub fn small_hit_rate(&self) -> f64 {
+        Self::ratio(self.small_queue_hits, self.lookups())
+    }
+
+    /// Share of lookups answered by the main queue.
+    pub fn main_hit_rate(&self) -> f64 {
+        Self::ratio(self.main_queue_hits, self.lookups())
+    }
+
+    /// Share of inserts that found their key in the ghost queue.
+    /// Consistently below 1% suggests the ghost queue is wasted memory;
+    /// above 50% suggests the main queue is too small.
+    pub fn ghost_hit_rate(&self) -> f64 {
+        Self::ratio(self.ghost_hits, self.ghost_hits + self.ghost_misses)
+    }
+
+    ///
+    /// Every counter in the Prometheus text format, one `name value` line
+    /// each, named `<prefix>_<counter>_total`, e.g. `s3fifo_hits_total 90`.
+    pub fn to_prometheus_text(&self, prefix: &str) -> String {
+        let mut text = String::new();
+        for (name, value) in self.counters() {
+            let _ = writeln!(text, "# TYPE {}_{}_total counter", prefix, name);
+            let _ = writeln!(text, "{}_{}_total {}", prefix, name, value);
+        }
+        text
+    }
+
+    fn counters(&self) -> [(&'static str, u64); 12] {
+        [
+            ("hits", self.hits),
+            ("misses", self.misses),
+            ("small_queue_hits", self.small_queue_hits),
+            ("main_queue_hits", self.main_queue_hits),
+            ("promotions_to_main", self.promotions_to_main),
+            ("ghost_hits", self.ghost_hits),
+            ("ghost_misses", self.ghost_misses),
+            ("prefetch_count", self.prefetch_count),
+            ("evictions_to_ghost", self.evictions_to_ghost),
+            ("full_evictions", self.full_evictions),
+            ("main_evictions", self.main_evictions),
+            ("small_evictions", self.small_evictions),
+        ]
+    }
+
+    ///
+    /// Share of entries leaving the small queue that were promoted rather
+    /// than evicted: `promotions_to_main / (promotions_to_main +
+    /// small_evictions)`. `small_evictions` covers entries retired to the
+    /// ghost queue as well as those dropped without one. A high value
+    /// suggests the small queue is too small to filter one-hit wonders.
+    pub fn small_eviction_promotion_rate(&self) -> f64 {
+        Self::ratio(self.promotions_to_main, self.promotions_to_main + self.small_evictions)
+    }
+
+    #[inline(always)]
+    fn ratio(part: u64, total: u64) -> f64 {
+        if total == 0 { 0.0 } else { part as f64 / total as f64 }
+    }
+}
+
+/// A single line for logs, e.g. `hits=90 misses=10 hit_rate=90.0% ...`.
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "hits={} misses={} hit_rate={:.1}% small_hits={} main_hits={} \
+             promotions={} evictions_to_ghost={} full_evictions={} \
+             small_evictions={} main_evictions={}",
+            self.hits, self.misses, self.hit_rate() * 100.0,
+            self.small_queue_hits, self.main_queue_hits,
+            self.promotions_to_main, self.evictions_to_ghost, self.full_evictions,
+            self.small_evictions, self.main_evictions
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_rates() {
+        let stats = CacheStats::default();
+        assert_eq!(stats.hit_rate(), 0.0);
+        assert_eq!(stats.small_hit_rate(), 0.0);
+        assert_eq!(stats.main_hit_rate(), 0.0);
+        assert_eq!(stats.ghost_hit_rate(), 0.0);
+        assert_eq!(stats.small_eviction_promotion_rate(), 0.0);
+    }
+
+    #[test]
+    fn rates() {
+        let stats = CacheStats {
+            hits: 3,
+            misses: 1,
+            small_queue_hits: 1,
+            main_queue_hits: 2,
+            ghost_hits: 1,
+            ghost_misses: 3,
+            ..Default::default()
+        };
+        assert_eq!(stats.lookups(), 4);
+        assert_eq!(stats.hit_rate(), 0.75);
+        assert_eq!(stats.small_hit_rate(), 0.25);
+        assert_eq!(stats.main_hit_rate(), 0.5);
+        assert_eq!(stats.ghost_hit_rate(), 0.25);
+    }
+
+    #[test]
+    fn display() {
+        let stats = CacheStats {
+            hits: 90,
+            misses: 10,
+            small_queue_hits: 30,
+            main_queue_hits: 60,
+            evictions_to_ghost: 5,
+            full_evictions: 3,
+            small_evictions: 6,
+            main_evictions: 2,
+            ..Default::default()
+        };
+        let line = stats.to_string();
+        assert!(!line.contains('\n'));
+        for part in [
+            "hits=90", "misses=10", "hit_rate=90.0%", "small_hits=30", "main_hits=60",
+            "evictions_to_ghost=5", "full_evictions=3", "small_evictions=6", "main_evictions=2",
+        ] {
+            assert!(line.contains(part), "{} missing from {}", part, line);
+        }
+    }
+
+    #[test]
+    fn prometheus_text() {
+        let stats = CacheStats { 
+            hits: 90, 
+            misses: 10, 
+            main_evictions: 4, 
+            small_evictions: 7, 
+            ..Default::default() 
+        };
+        let text = stats.to_prometheus_text("s3fifo");
+        let samples: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(samples.len(), 12);
+        for sample in &samples {
+            let (name, value) = sample.split_once(' ').unwrap();
+            assert!(name.starts_with("s3fifo_") && name.ends_with("_total"));
+            assert!(value.parse::<u64>().is_ok());
+        }
+        assert!(samples.contains(&"s3fifo_hits_total 90"));
+        assert!(samples.contains(&"s3fifo_misses_total 10"));
+        assert!(samples.contains(&"s3fifo_main_evictions_total 4"));
+        assert!(samples.contains(&"s3fifo_small_evictions_total 7"));
+    }
+}
@@ -0,0 +1,303 @@
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use http::header::CACHE_CONTROL;
+use http::{HeaderMap, Response};
+use tower::{Layer, Service};
+
+use crate::s3fifo::S3FIFO;
+
+///
+/// Maps a request to the key its response is cached under. Any
+/// `Fn(&Req) -> K` qualifies, e.g. `|req: &Request<()>| req.uri().to_string()`.
+pub trait KeyExtractor<Req, K>: Fn(&Req) -> K {}
+
+impl<Req, K, F> KeyExtractor<Req, K> for F where F: Fn(&Req) -> K {}
+
+///
+/// A cached response and when it stops being fresh. `S3FIFO` has no TTL
+/// of its own, so the expiry travels with the value and a stale hit is
+/// dropped on lookup.
+#[derive(Clone, Default)]
+pub struct CachedResponse<B> {
+    response: Response<B>,
+    expires_at: Option<Instant>,
+}
+
+impl<B> CachedResponse<B> {
+    #[inline(always)]
+    pub fn response(&self) -> &Response<B> {
+        &self.response
+    }
+
+    #[inline(always)]
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| Instant::now() >= at)
+    }
+}
+
+/// The cache shared by an `S3FIFOLayer` and every service it builds.
+pub type ResponseCache<K, B> = Arc<Mutex<S3FIFO<K, CachedResponse<B>>>>;
+
+///
+/// A `tower::Layer` answering repeated requests from an `S3FIFO`. Every
+/// service it builds shares one cache, so clones of a service (and
+/// services of the same layer) hit each other's entries.
+///
+/// Responses are cached unless their `Cache-Control` header says
+/// `no-store`, `no-cache`, `private` or `max-age=0`; a positive `max-age`
+/// sets their TTL, otherwise they stay until evicted. Errors from the
+/// inner service are not cached.
+pub struct S3FIFOLayer<K, B, F> {
+    cache: ResponseCache<K, B>,
+    key_extractor: Arc<F>,
+}
+
+impl<K, B, F> S3FIFOLayer<K, B, F> {
+    pub fn new(cache: S3FIFO<K, CachedResponse<B>>, key_extractor: F) -> S3FIFOLayer<K, B, F> {
+        Self::with_shared_cache(Arc::new(Mutex::new(cache)), key_extractor)
+    }
+
+    /// Like `new`, for a cache the caller keeps a handle to.
+    pub fn with_shared_cache(cache: ResponseCache<K, B>, key_extractor: F) -> S3FIFOLayer<K, B, F> {
+        S3FIFOLayer { cache, key_extractor: Arc::new(key_extractor) }
+    }
+
+    #[inline(always)]
+    pub fn cache(&self) -> &ResponseCache<K, B> {
+        &self.cache
+    }
+}
+
+impl<K, B, F> Clone for S3FIFOLayer<K, B, F> {
+    fn clone(&self) -> Self {
+        S3FIFOLayer { cache: Arc::clone(&self.cache), key_extractor: Arc::clone(&self.key_extractor) }
+    }
+}
+
+impl<S, K, B, F> Layer<S> for S3FIFOLayer<K, B, F> {
+    type Service = S3FIFOService<S, K, B, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        S3FIFOService {
+            inner,
+            cache: Arc::clone(&self.cache),
+            key_extractor: Arc::clone(&self.key_extractor),
+        }
+    }
+}
+
+/// The service built by `S3FIFOLayer`.
+pub struct S3FIFOService<S, K, B, F> {
+    inner: S,
+    cache: ResponseCache<K, B>,
+    key_extractor: Arc<F>,
+}
+
+impl<S, K, B, F> Clone for S3FIFOService<S, K, B, F>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        S3FIFOService {
+            inner: self.inner.clone(),
+            cache: Arc::clone(&self.cache),
+            key_extractor: Arc::clone(&self.key_extractor),
+        }
+    }
+}
+
+impl<S, K, B, F> S3FIFOService<S, K, B, F>
+where
+    K: Clone + Eq + Hash,
+    B: Clone,
+{
+    /// The fresh cached response for `key`, dropping a stale one.
+    fn lookup(&self, key: &K) -> Option<Response<B>> {
+        let mut cache = self.cache.lock().expect("response cache lock poisoned");
+        let cached = cache.get(key)?;
+        if !cached.is_expired() {
+            return Some(cached.response.clone());
+        }
+        cache.take(key);
+        None
+    }
+}
+
+impl<S, Req, K, B, F> Service<Req> for S3FIFOService<S, K, B, F>
+where
+    S: Service<Req, Response = Response<B>>,
+    S::Future: Send + 'static,
+    K: Clone + Eq + Hash + Send + 'static,
+    B: Clone + Send + 'static,
+    F: KeyExtractor<Req, K>,
+{
+    type Response = Response<B>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<B>, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let key = (self.key_extractor)(&request);
+        if let Some(response) = self.lookup(&key) {
+            return Box::pin(async move { Ok(response) });
+        }
+
+        let cache = Arc::clone(&self.cache);
+        let pending = self.inner.call(request);
+        Box::pin(async move {
+            let response = pending.await?;
+            if let Some(ttl) = cache_ttl(response.headers()) {
+                let cached = CachedResponse {
+                    response: response.clone(),
+                    expires_at: ttl.map(|ttl| Instant::now() + ttl),
+                };
+                cache.lock().expect("response cache lock poisoned").put(key, cached);
+            }
+            Ok(response)
+        })
+    }
+}
+
+///
+/// How long a response may be cached according to its `Cache-Control`
+/// header: `None` if it must not be, `Some(None)` if it may be with no
+/// expiry (no header, or no `max-age`).
+fn cache_ttl(headers: &HeaderMap) -> Option<Option<Duration>> {
+    let mut ttl = None;
+    for value in headers.get_all(CACHE_CONTROL) {
+        let Ok(value) = value.to_str() else { continue };
+        for directive in value.split(',').map(|directive| directive.trim().to_ascii_lowercase()) {
+            match directive.split_once('=') {
+                None if matches!(directive.as_str(), "no-store" | "no-cache" | "private") => return None,
+                Some(("max-age", seconds)) => {
+                    let seconds: u64 = seconds.trim_matches('"').parse().ok()?;
+                    if seconds == 0 {
+                        return None;
+                    }
+                    ttl = Some(Duration::from_secs(seconds));
+                }
+                _ => {}
+            }
+        }
+    }
+    Some(ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderValue, Request};
+    use tower_test::mock;
+    use super::*;
+
+    type Mock = mock::Mock<Request<()>, Response<String>>;
+    type Handle = mock::Handle<Request<()>, Response<String>>;
+    type ByUri = fn(&Request<()>) -> String;
+    type Cached = S3FIFOService<Mock, String, String, ByUri>;
+
+    fn by_uri(request: &Request<()>) -> String {
+        request.uri().to_string()
+    }
+
+    fn cached_service() -> (Cached, Handle) {
+        let (mock, handle) = mock::pair();
+        let layer = S3FIFOLayer::new(
+            S3FIFO::try_new(10, 0.5).unwrap(),
+            by_uri as ByUri,
+        );
+        (layer.layer(mock), handle)
+    }
+
+    fn get(uri: &str) -> Request<()> {
+        Request::get(uri).body(()).unwrap()
+    }
+
+    /// Answer the next request reaching the inner service with `body`.
+    async fn respond(handle: &mut Handle, body: &str, cache_control: Option<&'static str>) -> Request<()> {
+        let (request, send) = handle.next_request().await.expect("inner service was not called");
+        let mut response = Response::new(body.to_string());
+        if let Some(value) = cache_control {
+            response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static(value));
+        }
+        send.send_response(response);
+        request
+    }
+
+    async fn call(service: &mut Cached, uri: &str) -> String {
+        std::future::poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+        service.call(get(uri)).await.unwrap().into_body()
+    }
+
+    #[tokio::test]
+    async fn duplicate_requests_hit_the_cache() {
+        let (mut service, mut handle) = cached_service();
+
+        let (body, request) = tokio::join!(call(&mut service, "/a"), respond(&mut handle, "a", None));
+        assert_eq!(body, "a");
+        assert_eq!(request.uri(), "/a");
+
+        // Served without reaching the inner service.
+        assert_eq!(call(&mut service, "/a").await, "a");
+        assert_eq!(call(&mut service, "/a").await, "a");
+
+        let (body, _) = tokio::join!(call(&mut service, "/b"), respond(&mut handle, "b", Some("max-age=60")));
+        assert_eq!(body, "b");
+        assert_eq!(call(&mut service, "/b").await, "b");
+
+        drop(service);
+        assert!(handle.next_request().await.is_none(), "a duplicate request reached the inner service");
+    }
+
+    #[tokio::test]
+    async fn uncacheable_responses_go_to_the_inner_service() {
+        let (mut service, mut handle) = cached_service();
+
+        for _ in 0..2 {
+            let (body, _) = tokio::join!(call(&mut service, "/a"), respond(&mut handle, "a", Some("no-store")));
+            assert_eq!(body, "a");
+        }
+        for _ in 0..2 {
+            let (body, _) = tokio::join!(call(&mut service, "/b"), respond(&mut handle, "b", Some("public, max-age=0")));
+            assert_eq!(body, "b");
+        }
+        assert!(service.cache.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn expired_responses_are_dropped() {
+        let (service, _handle) = cached_service();
+        let stale = CachedResponse {
+            response: Response::new("stale".to_string()),
+            expires_at: Some(Instant::now()),
+        };
+        service.cache.lock().unwrap().put("/a".to_string(), stale);
+
+        assert!(service.lookup(&"/a".to_string()).is_none());
+        assert!(service.cache.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ttl_from_cache_control() {
+        fn ttl(value: &'static str) -> Option<Option<Duration>> {
+            let mut headers = HeaderMap::new();
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static(value));
+            cache_ttl(&headers)
+        }
+
+        assert_eq!(cache_ttl(&HeaderMap::new()), Some(None));
+        assert_eq!(ttl("public"), Some(None));
+        assert_eq!(ttl("public, max-age=30"), Some(Some(Duration::from_secs(30))));
+        assert_eq!(ttl("Max-Age=5"), Some(Some(Duration::from_secs(5))));
+        assert_eq!(ttl("max-age=0"), None);
+        assert_eq!(ttl("max-age=soon"), None);
+        assert_eq!(ttl("no-store"), None);
+        assert_eq!(ttl("max-age=30, private"), None);
+    }
+}
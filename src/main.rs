@@ -1,3 +1,5 @@
+use std::num::NonZeroUsize;
+
 use s3fifo::prelude::*;
 // check lib/lib.rs for prelude content
 
@@ -6,7 +8,7 @@ use s3fifo::prelude::*;
 fn main() {
     // it's same as 
     // `let mut cache: S3FIFO<usize, usize> = S3FIFO::new_with_default_ratio(100);`
-    let mut cache: S3FIFO<usize, usize> = S3FIFO::new(100, 0.1);
+    let mut cache: S3FIFO<usize, usize> = S3FIFO::new(NonZeroUsize::new(100).unwrap(), 0.1);
 
     cache.put(0, 100);
     cache.put(1, 101);